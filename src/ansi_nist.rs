@@ -0,0 +1,9 @@
+//! Module for the fingerprint records of ANSI/NIST-ITL transactions
+//
+// A transaction is a sequence of logical records, each starting with its length. Type-4 records
+// carry high resolution grey scale fingerprints behind a fixed binary header, Type-14 records
+// carry fingerprints of any resolution in ASCII tagged fields.
+pub mod tagged;
+pub mod type4;
+pub mod type14;
+pub mod transaction;
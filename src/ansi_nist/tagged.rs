@@ -0,0 +1,202 @@
+//! Tagged-field records, the ASCII records of a transaction
+//
+// Each field is written as `<record type>.<field number>:<value>`, fields are separated by GS and
+// the record ends with FS. Values may hold subfields separated by RS, each of information items
+// separated by US. Field 1 is the record length LEN, counting every byte up to and including the
+// final FS, and field 999, always the last one, holds binary image data that may contain any byte.
+use std::io;
+
+/// File separator, ends a record
+pub const FS: u8 = 0x1C;
+/// Group separator, between fields
+pub const GS: u8 = 0x1D;
+/// Record separator, between subfields
+pub const RS: u8 = 0x1E;
+/// Unit separator, between information items
+pub const US: u8 = 0x1F;
+
+pub const LEN: u16 = 1;
+pub const IDC: u16 = 2;
+/// The field holding binary image data
+pub const DATA: u16 = 999;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub number: u16,
+    pub value: Vec<u8>,
+}
+
+impl Field {
+    pub fn new(number: u16, value: impl Into<Vec<u8>>) -> Self {
+        Self { number, value: value.into() }
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.value).into_owned()
+    }
+
+    /// The information items of every subfield.
+    pub fn subfields(&self) -> Vec<Vec<String>> {
+        self.text().split(RS as char).map(|s| s.split(US as char).map(str::to_string).collect()).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedRecord {
+    pub record_type: u8,
+    /// The fields in the order they are written, LEN included
+    pub fields: Vec<Field>,
+}
+
+impl TaggedRecord {
+    /// A record holding only LEN and IDC.
+    pub fn new(record_type: u8, idc: u8) -> Self {
+        Self { record_type, fields: vec![Field::new(LEN, "0"), Field::new(IDC, format!("{:02}", idc))] }
+    }
+
+    pub fn field(&self, number: u16) -> Option<&Field> {
+        self.fields.iter().find(|f| f.number == number)
+    }
+
+    pub fn text(&self, number: u16) -> Option<String> {
+        self.field(number).map(|f| f.text())
+    }
+
+    /// The value of a numeric field.
+    pub fn number<T: std::str::FromStr>(&self, number: u16) -> io::Result<T> {
+        let text = self.text(number)
+            .ok_or_else(|| invalid_data(format!("Type-{} record has no field {}", self.record_type, number)))?;
+        text.trim().parse()
+            .map_err(|_| invalid_data(format!("Field {}.{:03} is not a number: {}", self.record_type, number, text)))
+    }
+
+    pub fn idc(&self) -> io::Result<u8> {
+        self.number(IDC)
+    }
+
+    /// Replace the value of a field, or add it keeping the fields ordered by number with the image
+    /// data last.
+    pub fn set(&mut self, number: u16, value: impl Into<Vec<u8>>) {
+        let value = value.into();
+        if let Some(field) = self.fields.iter_mut().find(|f| f.number == number) {
+            field.value = value;
+            return;
+        }
+        let position = self.fields.iter().position(|f| f.number > number).unwrap_or(self.fields.len());
+        self.fields.insert(position, Field::new(number, value));
+    }
+
+    pub fn remove(&mut self, number: u16) -> Option<Field> {
+        let position = self.fields.iter().position(|f| f.number == number)?;
+        Some(self.fields.remove(position))
+    }
+
+    /// Parse the record at the start of `contents`, bytes after its length are ignored.
+    pub fn parse(contents: &[u8]) -> io::Result<Self> {
+        let mut fields = vec![];
+        let mut record_type = None;
+        let mut len = contents.len();
+        let mut position = 0;
+        while position < len {
+            let colon = contents[position..len].iter().position(|b| *b == b':')
+                .map(|p| position + p)
+                .ok_or_else(|| invalid_data(format!("Expected a field tag at byte {}", position)))?;
+            let tag = std::str::from_utf8(&contents[position..colon]).ok()
+                .and_then(|tag| tag.split_once('.'))
+                .and_then(|(t, n)| Some((t.trim().parse::<u8>().ok()?, n.trim().parse::<u16>().ok()?)))
+                .ok_or_else(|| invalid_data(format!("Invalid field tag at byte {}", position)))?;
+            let expected = *record_type.get_or_insert(tag.0);
+            if expected != tag.0 {
+                return Err(invalid_data(format!("Field {}.{:03} in a Type-{} record", tag.0, tag.1, expected)));
+            }
+            let start = colon + 1;
+            let end = if tag.1 == DATA {
+                // binary data runs to the FS ending the record
+                len.saturating_sub(1).max(start)
+            } else {
+                contents[start..len].iter().position(|b| *b == GS || *b == FS).map_or(len, |p| start + p)
+            };
+            let field = Field::new(tag.1, &contents[start..end]);
+            if fields.is_empty() {
+                if field.number != LEN {
+                    return Err(invalid_data("A tagged record starts with its length".to_string()));
+                }
+                len = field.text().trim().parse::<usize>().ok()
+                    .filter(|len| *len > end && *len <= contents.len())
+                    .ok_or_else(|| invalid_data(format!("Record length {} out of range", field.text())))?;
+            }
+            fields.push(field);
+            match contents.get(end) {
+                Some(&GS) if end + 1 < len => position = end + 1,
+                Some(&FS) if end + 1 == len => break,
+                _ => return Err(invalid_data(format!("Expected a separator at byte {}", end))),
+            }
+        }
+        Ok(Self { record_type: record_type.unwrap_or(0), fields })
+    }
+
+    /// The encoded record with LEN set to its length.
+    pub fn encode(&self) -> Vec<u8> {
+        let encode_with = |len: usize| {
+            let mut contents = vec![];
+            for (i, field) in self.fields.iter().enumerate() {
+                if i > 0 {
+                    contents.push(GS);
+                }
+                contents.extend_from_slice(format!("{}.{:03}:", self.record_type, field.number).as_bytes());
+                if field.number == LEN {
+                    contents.extend_from_slice(len.to_string().as_bytes());
+                } else {
+                    contents.extend_from_slice(&field.value);
+                }
+            }
+            contents.push(FS);
+            contents
+        };
+        // the length counts its own digits
+        let mut len = 0;
+        loop {
+            let contents = encode_with(len);
+            if contents.len() == len {
+                return contents;
+            }
+            len = contents.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TaggedRecord, FS, GS, RS, US};
+
+    #[test]
+    fn test_round_trip() {
+        let mut record = TaggedRecord::new(2, 3);
+        record.set(5, "A");
+        record.set(4, [b'x', RS, b'y', US, b'z']);
+        record.set(999, [0, FS, GS, 0xFF]);
+        let contents = record.encode();
+        assert!(contents.starts_with(b"2.001:49\x1d2.002:03\x1d2.004:x"));
+        assert_eq!(49, contents.len());
+        let parsed = TaggedRecord::parse(&[&contents[..], b"trailing"].concat()).unwrap();
+        assert_eq!(vec![1, 2, 4, 5, 999], parsed.fields.iter().map(|f| f.number).collect::<Vec<_>>());
+        assert_eq!(vec![vec!["x".to_string()], vec!["y".to_string(), "z".to_string()]], parsed.field(4).unwrap().subfields());
+        assert_eq!(vec![0, FS, GS, 0xFF], parsed.field(999).unwrap().value);
+        assert_eq!(49, parsed.number::<usize>(1).unwrap());
+        assert_eq!(3, parsed.idc().unwrap());
+        assert_eq!(contents, parsed.encode());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(TaggedRecord::parse(b"2.002:00\x1c").is_err());
+        assert!(TaggedRecord::parse(b"2.001:40\x1d2.002:00\x1c").is_err());
+        let error = TaggedRecord::parse(b"2.001:18\x1d4.002:00\x1c").unwrap_err();
+        assert_eq!("Field 4.002 in a Type-2 record", error.to_string());
+        assert!(TaggedRecord::parse(b"2.001:18\x1d2.002:00\x1c").is_ok());
+    }
+}
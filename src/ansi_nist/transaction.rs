@@ -0,0 +1,236 @@
+//! Transactions, the files (.eft, .an2) holding a Type-1 record and the records it lists
+//
+// The Type-1 record comes first and its content field CNT, 1.003, lists the records that follow:
+// a first subfield of 1 and the number of other records, then one subfield per record with its
+// type and IDC. Types 3 to 8 are binary records starting with a four byte length followed by the
+// IDC, all other types are tagged-field records. Records other than Type-4 and Type-14 are kept
+// as they are.
+use std::io;
+
+use crate::ansi_nist::tagged::{TaggedRecord, LEN, RS, US};
+use crate::ansi_nist::type14::Type14Record;
+use crate::ansi_nist::type4::Type4Record;
+use crate::gray::GrayImage;
+
+/// The content field of the Type-1 record
+pub const CNT: u16 = 3;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn is_binary(record_type: u8) -> bool {
+    (3..=8).contains(&record_type)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Type4(Type4Record),
+    Type14(Type14Record),
+    /// Any other tagged-field record, such as the Type-2 descriptive text
+    Tagged(TaggedRecord),
+    /// Any other binary record, its length included
+    Binary(u8, Vec<u8>),
+}
+
+impl Record {
+    pub fn record_type(&self) -> u8 {
+        match self {
+            Record::Type4(_) => 4,
+            Record::Type14(_) => 14,
+            Record::Tagged(record) => record.record_type,
+            Record::Binary(record_type, _) => *record_type,
+        }
+    }
+
+    pub fn idc(&self) -> io::Result<u8> {
+        match self {
+            Record::Type4(record) => Ok(record.idc),
+            Record::Type14(record) => record.idc(),
+            Record::Tagged(record) => record.idc(),
+            Record::Binary(_, contents) => contents.get(4).copied()
+                .ok_or_else(|| invalid_data("Binary record too short for an IDC".to_string())),
+        }
+    }
+
+    /// The image of a Type-4 or Type-14 record.
+    pub fn to_image(&self) -> Option<io::Result<GrayImage>> {
+        match self {
+            Record::Type4(record) => Some(record.to_image()),
+            Record::Type14(record) => Some(record.to_image()),
+            _ => None,
+        }
+    }
+
+    /// Parse a record of `record_type` at the start of `contents`, returning it and its length.
+    pub fn parse(record_type: u8, contents: &[u8]) -> io::Result<(Self, usize)> {
+        if is_binary(record_type) {
+            let len = contents.get(..4).map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+                .filter(|len| (5..=contents.len()).contains(len))
+                .ok_or_else(|| invalid_data(format!("Type-{} record length out of range", record_type)))?;
+            let record = match record_type {
+                4 => Record::Type4(Type4Record::parse(contents)?),
+                _ => Record::Binary(record_type, contents[..len].to_vec()),
+            };
+            return Ok((record, len));
+        }
+        let record = TaggedRecord::parse(contents)?;
+        if record.record_type != record_type {
+            return Err(invalid_data(format!("Expected a Type-{} record, found Type-{}", record_type, record.record_type)));
+        }
+        let len = record.number(LEN)?;
+        Ok((match record_type {
+            14 => Record::Type14(Type14Record { record }),
+            _ => Record::Tagged(record),
+        }, len))
+    }
+
+    /// The encoded record with its length recomputed.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Record::Type4(record) => record.encode(),
+            Record::Type14(record) => record.encode(),
+            Record::Tagged(record) => record.encode(),
+            Record::Binary(_, contents) => {
+                let mut contents = contents.clone();
+                let len = (contents.len() as u32).to_be_bytes();
+                contents[..4].copy_from_slice(&len);
+                contents
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// The Type-1 record, its CNT field is rewritten from the records on encoding
+    pub header: TaggedRecord,
+    pub records: Vec<Record>,
+}
+
+impl Transaction {
+    pub fn parse(contents: &[u8]) -> io::Result<Self> {
+        let header = TaggedRecord::parse(contents)?;
+        if header.record_type != 1 {
+            return Err(invalid_data(format!("A transaction starts with a Type-1 record, found Type-{}", header.record_type)));
+        }
+        let cnt = header.field(CNT).ok_or_else(|| invalid_data("Type-1 record has no CNT field".to_string()))?;
+        let mut position: usize = header.number(LEN)?;
+        let mut records = vec![];
+        for subfield in cnt.subfields().iter().skip(1) {
+            let record_type = subfield.first().and_then(|t| t.trim().parse::<u8>().ok())
+                .ok_or_else(|| invalid_data(format!("Invalid CNT entry {:?}", subfield)))?;
+            let (record, len) = Record::parse(record_type, &contents[position..])
+                .map_err(|e| invalid_data(format!("Record {} at byte {}: {}", records.len() + 2, position, e)))?;
+            records.push(record);
+            position += len;
+        }
+        if position < contents.len() {
+            log::warn!("Ignoring {} bytes after the last record", contents.len() - position);
+        }
+        Ok(Self { header, records })
+    }
+
+    /// The CNT field listing the records.
+    fn content(&self) -> io::Result<Vec<u8>> {
+        let mut cnt = format!("1{}{}", US as char, self.records.len());
+        for record in &self.records {
+            cnt.push_str(&format!("{}{}{}{:02}", RS as char, record.record_type(), US as char, record.idc()?));
+        }
+        Ok(cnt.into_bytes())
+    }
+
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut header = self.header.clone();
+        header.set(CNT, self.content()?);
+        let mut contents = header.encode();
+        for record in &self.records {
+            contents.extend(record.encode());
+        }
+        Ok(contents)
+    }
+
+    /// The Type-4 and Type-14 records with their position in `records`.
+    pub fn image_records(&self) -> impl Iterator<Item = (usize, &Record)> {
+        self.records.iter().enumerate().filter(|(_, record)| matches!(record, Record::Type4(_) | Record::Type14(_)))
+    }
+
+    /// Replace the image of the Type-4 or Type-14 record at `index`, compressed to WSQ at `bitrate`,
+    /// keeping its IDC and finger description.
+    pub fn replace_image(&mut self, index: usize, image: &GrayImage, bitrate: f64) -> io::Result<()> {
+        match self.records.get_mut(index) {
+            Some(Record::Type4(record)) => record.set_image(image, bitrate),
+            Some(Record::Type14(record)) => record.set_image(image, bitrate),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Record {} holds no image", index))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Record, Transaction, CNT};
+    use crate::ansi_nist::tagged::{TaggedRecord, RS, US};
+    use crate::ansi_nist::type14::{Type14Record, FCD};
+    use crate::ansi_nist::type4::Type4Record;
+    use crate::codec::tests::fingerprint;
+    use crate::codec::DEFAULT_BITRATE;
+
+    fn transaction() -> Transaction {
+        let mut header = TaggedRecord::new(1, 0);
+        header.set(2, "0400");
+        header.set(CNT, "");
+        header.set(4, "CAR");
+        header.set(8, "NY0303000");
+        let mut text = TaggedRecord::new(2, 1);
+        text.set(18, "DOE, JOHN");
+        let image = fingerprint(260, 280).with_ppi(500);
+        let mut finger = Type14Record::from_image(&image, 3, "NY0303000", DEFAULT_BITRATE).unwrap();
+        finger.record.set(FCD, "20260101");
+        Transaction {
+            header,
+            records: vec![
+                Record::Tagged(text),
+                Record::Type4(Type4Record::from_image(&image, 2, DEFAULT_BITRATE).unwrap()),
+                Record::Binary(7, vec![0, 0, 0, 0, 4, 1, 2, 3]),
+                Record::Type14(finger),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let contents = transaction().encode().unwrap();
+        let parsed = Transaction::parse(&contents).unwrap();
+        let cnt = parsed.header.field(CNT).unwrap().text();
+        let (us, rs) = (US as char, RS as char);
+        assert_eq!(format!("1{us}4{rs}2{us}01{rs}4{us}02{rs}7{us}04{rs}14{us}03"), cnt);
+        assert_eq!(vec![2, 4, 7, 14], parsed.records.iter().map(|r| r.record_type()).collect::<Vec<_>>());
+        assert_eq!(vec![1, 3], parsed.image_records().map(|(i, _)| i).collect::<Vec<_>>());
+        assert_eq!(Record::Binary(7, vec![0, 0, 0, 8, 4, 1, 2, 3]), parsed.records[2]);
+        assert_eq!(contents, parsed.encode().unwrap());
+        for (_, record) in parsed.image_records() {
+            assert_eq!(280, record.to_image().unwrap().unwrap().height);
+        }
+    }
+
+    #[test]
+    fn test_replace_image() {
+        let mut transaction = transaction();
+        let image = fingerprint(300, 200);
+        transaction.replace_image(1, &image, DEFAULT_BITRATE).unwrap();
+        transaction.replace_image(3, &image, DEFAULT_BITRATE).unwrap();
+        assert!(transaction.replace_image(0, &image, DEFAULT_BITRATE).is_err());
+        let parsed = Transaction::parse(&transaction.encode().unwrap()).unwrap();
+        for (_, record) in parsed.image_records() {
+            let decoded = record.to_image().unwrap().unwrap();
+            assert_eq!((300, 200), (decoded.width, decoded.height));
+        }
+        assert_eq!(3, parsed.records[3].idc().unwrap());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let contents = transaction().encode().unwrap();
+        assert!(Transaction::parse(&contents[..contents.len() - 10]).is_err());
+    }
+}
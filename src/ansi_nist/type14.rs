@@ -0,0 +1,249 @@
+//! Type-14 variable resolution fingerprint records
+//
+// A tagged-field record whose image data is in field 14.999. The fields describing it:
+//     14.003  IMP   impression type
+//     14.004  SRC   source agency, the originating agency identifier
+//     14.005  FCD   fingerprint capture date, YYYYMMDD
+//     14.006  HLL   horizontal line length, the width in pixels
+//     14.007  VLL   vertical line length, the height in pixels
+//     14.008  SLC   scale units, 0 for none, 1 for pixels per inch, 2 for pixels per centimeter
+//     14.009  THPS  transmitted horizontal pixel scale
+//     14.010  TVPS  transmitted vertical pixel scale
+//     14.011  CGA   compression algorithm, NONE or WSQ20 (WSQ in older editions of the standard)
+//     14.012  BPX   bits per pixel
+//     14.013  FGP   finger position
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ansi_nist::tagged::{TaggedRecord, DATA};
+use crate::codec;
+use crate::gray::GrayImage;
+
+pub const RECORD_TYPE: u8 = 14;
+
+pub const IMP: u16 = 3;
+pub const SRC: u16 = 4;
+pub const FCD: u16 = 5;
+pub const HLL: u16 = 6;
+pub const VLL: u16 = 7;
+pub const SLC: u16 = 8;
+pub const THPS: u16 = 9;
+pub const TVPS: u16 = 10;
+pub const CGA: u16 = 11;
+pub const BPX: u16 = 12;
+pub const FGP: u16 = 13;
+
+/// CGA of uncompressed pixels
+pub const UNCOMPRESSED: &str = "NONE";
+/// CGA of WSQ compressed data
+pub const WSQ: &str = "WSQ20";
+/// The resolution written when the image does not record one
+pub const DEFAULT_PPI: u16 = 500;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Today as YYYYMMDD, in UTC.
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+    // civil date from days since 1970-01-01, in 400 year eras starting on March 1st
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Type14Record {
+    pub record: TaggedRecord,
+}
+
+impl Type14Record {
+    /// A record of 8-bit pixels or compressed data for an unknown finger captured today, originating
+    /// from the agency identified by `source_agency`.
+    pub fn new(idc: u8, source_agency: &str, width: usize, height: usize, ppi: u16, compression: &str, data: Vec<u8>) -> io::Result<Self> {
+        if source_agency.trim().is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Type-14 record needs a source agency"));
+        }
+        let mut record = Self { record: TaggedRecord::new(RECORD_TYPE, idc) };
+        record.record.set(IMP, "0");
+        record.record.set(SRC, source_agency);
+        record.record.set(FCD, today());
+        record.set_image_fields(width, height, ppi, compression, data);
+        record.record.set(FGP, "0");
+        Ok(record)
+    }
+
+    fn set_image_fields(&mut self, width: usize, height: usize, ppi: u16, compression: &str, data: Vec<u8>) {
+        self.record.set(HLL, width.to_string());
+        self.record.set(VLL, height.to_string());
+        self.record.set(SLC, "1");
+        self.record.set(THPS, ppi.to_string());
+        self.record.set(TVPS, ppi.to_string());
+        self.record.set(CGA, compression);
+        self.record.set(BPX, "8");
+        self.record.set(DATA, data);
+    }
+
+    /// A record of `image` compressed to WSQ at `bitrate`, for an unknown finger captured today.
+    pub fn from_image(image: &GrayImage, idc: u8, source_agency: &str, bitrate: f64) -> io::Result<Self> {
+        let data = codec::encode(image, bitrate)?;
+        Self::new(idc, source_agency, image.width, image.height, image.ppi.unwrap_or(DEFAULT_PPI), WSQ, data)
+    }
+
+    /// Replace the image with `image` compressed to WSQ at `bitrate`, keeping the other fields.
+    pub fn set_image(&mut self, image: &GrayImage, bitrate: f64) -> io::Result<()> {
+        let data = codec::encode(image, bitrate)?;
+        self.set_image_fields(image.width, image.height, image.ppi.unwrap_or(DEFAULT_PPI), WSQ, data);
+        Ok(())
+    }
+
+    pub fn with_finger_position(mut self, position: u8) -> Self {
+        self.record.set(FGP, position.to_string());
+        self
+    }
+
+    pub fn with_impression_type(mut self, impression_type: u8) -> Self {
+        self.record.set(IMP, impression_type.to_string());
+        self
+    }
+
+    pub fn impression_type(&self) -> io::Result<u8> {
+        self.record.number(IMP)
+    }
+
+    /// The first finger position.
+    pub fn finger_position(&self) -> io::Result<u8> {
+        let fgp = self.record.field(FGP).ok_or_else(|| invalid_data("Type-14 record has no finger position".to_string()))?;
+        let first = fgp.subfields().first().and_then(|items| items.first().cloned()).unwrap_or_default();
+        first.trim().parse().map_err(|_| invalid_data(format!("Invalid finger position {}", first)))
+    }
+
+    pub fn idc(&self) -> io::Result<u8> {
+        self.record.idc()
+    }
+
+    pub fn source_agency(&self) -> Option<String> {
+        self.record.text(SRC).map(|agency| agency.trim().to_string()).filter(|agency| !agency.is_empty())
+    }
+
+    pub fn width(&self) -> io::Result<usize> {
+        self.record.number(HLL)
+    }
+
+    pub fn height(&self) -> io::Result<usize> {
+        self.record.number(VLL)
+    }
+
+    /// The compression algorithm, upper case.
+    pub fn compression(&self) -> io::Result<String> {
+        self.record.text(CGA).map(|cga| cga.trim().to_ascii_uppercase())
+            .ok_or_else(|| invalid_data("Type-14 record has no compression algorithm".to_string()))
+    }
+
+    /// The horizontal resolution in pixels per inch, if the record has a scale.
+    pub fn ppi(&self) -> io::Result<Option<u16>> {
+        let scale: f64 = self.record.number(THPS)?;
+        Ok(match self.record.number::<u8>(SLC)? {
+            1 => Some(scale.round() as u16),
+            2 => Some((scale * 2.54).round() as u16),
+            _ => None,
+        })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.record.field(DATA).map_or(&[], |field| &field.value)
+    }
+
+    /// The image of the record, decompressed when it is WSQ.
+    pub fn to_image(&self) -> io::Result<GrayImage> {
+        let (width, height) = (self.width()?, self.height()?);
+        let mut image = match self.compression()?.as_str() {
+            UNCOMPRESSED => {
+                let bits: u8 = self.record.number(BPX)?;
+                if bits != 8 {
+                    return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} bits per pixel are not supported", bits)));
+                }
+                GrayImage::new(width, height, self.data().to_vec()).map_err(|e| invalid_data(e.to_string()))?
+            },
+            WSQ | "WSQ" => codec::decode(self.data())?,
+            cga => return Err(io::Error::new(io::ErrorKind::Unsupported, format!("Compression algorithm {} is not supported", cga))),
+        };
+        if (image.width, image.height) != (width, height) {
+            return Err(invalid_data(format!("Record is {}x{} but its image is {}x{}", width, height, image.width, image.height)));
+        }
+        if let Some(ppi) = self.ppi()? {
+            image.ppi = Some(ppi);
+        }
+        Ok(image)
+    }
+
+    pub fn parse(contents: &[u8]) -> io::Result<Self> {
+        let record = TaggedRecord::parse(contents)?;
+        if record.record_type != RECORD_TYPE {
+            return Err(invalid_data(format!("Expected a Type-14 record, found Type-{}", record.record_type)));
+        }
+        Ok(Self { record })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.record.encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{today, Type14Record, FCD, WSQ};
+    use crate::codec::tests::fingerprint;
+    use crate::codec::DEFAULT_BITRATE;
+    use crate::gray::GrayImage;
+
+    #[test]
+    fn test_wsq_record() {
+        let image = fingerprint(300, 320).with_ppi(1000);
+        let record = Type14Record::from_image(&image, 5, "NY0303000", DEFAULT_BITRATE).unwrap();
+        let contents = record.encode();
+        let text = String::from_utf8_lossy(&contents);
+        assert!(text.starts_with(&format!("14.001:{}\x1d14.002:05\x1d14.003:0\x1d14.004:NY0303000\x1d14.005:", contents.len())));
+        assert!(text.contains("\x1d14.006:300\x1d14.007:320\x1d14.008:1\x1d14.009:1000\x1d14.010:1000\x1d14.011:WSQ20\x1d"));
+        assert!(text.contains("\x1d14.999:\u{fffd}\u{fffd}"));
+        assert_eq!(8, record.record.text(FCD).unwrap().len());
+
+        let parsed = Type14Record::parse(&contents).unwrap();
+        assert_eq!(contents, parsed.encode());
+        assert_eq!(WSQ, parsed.compression().unwrap());
+        assert_eq!(Some("NY0303000".to_string()), parsed.source_agency());
+        let decoded = parsed.to_image().unwrap();
+        assert_eq!((300, 320, Some(1000)), (decoded.width, decoded.height, decoded.ppi));
+    }
+
+    #[test]
+    fn test_source_agency_required() {
+        let image = fingerprint(64, 64);
+        for agency in ["", "  "] {
+            let error = Type14Record::from_image(&image, 1, agency, DEFAULT_BITRATE).unwrap_err();
+            assert_eq!(std::io::ErrorKind::InvalidInput, error.kind());
+        }
+    }
+
+    #[test]
+    fn test_uncompressed_record() {
+        let contents = b"14.001:111\x1d14.002:00\x1d14.003:1\x1d14.006:3\x1d14.007:1\x1d14.008:2\x1d14.009:394\x1d14.010:394\x1d14.011:none\x1d14.012:8\x1d14.999:\x1c\x1d\x1e\x1c";
+        let image = Type14Record::parse(contents).unwrap().to_image().unwrap();
+        assert_eq!(GrayImage::new(3, 1, vec![0x1c, 0x1d, 0x1e]).unwrap().with_ppi(1001), image);
+    }
+
+    #[test]
+    fn test_today() {
+        let date = today();
+        assert_eq!(8, date.len());
+        assert!(date.as_str() >= "20240101" && date[4..6].parse::<u8>().unwrap() <= 12);
+    }
+}
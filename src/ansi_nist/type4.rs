@@ -0,0 +1,174 @@
+//! Type-4 high resolution grey scale fingerprint records
+//
+// The record is an 18 byte binary header followed by the image data:
+//     LEN  4 bytes  record length, header included
+//     IDC  1 byte   information designation character, referenced by the Type-1 CNT field
+//     IMP  1 byte   impression type
+//     FGP  6 bytes  possible finger positions, unused ones set to 255
+//     ISR  1 byte   0 for the minimum scanning resolution, 1 for the native resolution
+//     HLL  2 bytes  horizontal line length, the width in pixels
+//     VLL  2 bytes  vertical line length, the height in pixels
+//     GCA  1 byte   grey scale compression algorithm, 1 for WSQ
+// All numbers are unsigned and most significant byte first.
+use std::io;
+
+use crate::codec;
+use crate::gray::GrayImage;
+
+pub const HEADER_LEN: usize = 18;
+/// GCA of uncompressed 8-bit pixels
+pub const UNCOMPRESSED: u8 = 0;
+/// GCA of WSQ compressed data
+pub const WSQ: u8 = 1;
+/// FGP of an unused finger position
+pub const UNUSED_POSITION: u8 = 255;
+/// The minimum scanning resolution of 19.69 pixels per millimeter that ISR 0 stands for
+pub const MINIMUM_SCANNING_RESOLUTION_PPI: u16 = 500;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Type4Record {
+    pub idc: u8,
+    pub impression_type: u8,
+    pub finger_positions: [u8; 6],
+    /// ISR, whether the image is at its native scanning resolution rather than the minimum one
+    pub native_resolution: bool,
+    pub width: u16,
+    pub height: u16,
+    pub compression: u8,
+    pub data: Vec<u8>,
+}
+
+impl Type4Record {
+    /// A record of `image` compressed to WSQ at `bitrate`, for an unknown finger.
+    pub fn from_image(image: &GrayImage, idc: u8, bitrate: f64) -> io::Result<Self> {
+        let data = codec::encode(image, bitrate)?;
+        Ok(Self {
+            idc,
+            impression_type: 0,
+            finger_positions: [0, UNUSED_POSITION, UNUSED_POSITION, UNUSED_POSITION, UNUSED_POSITION, UNUSED_POSITION],
+            native_resolution: image.ppi.is_some_and(|ppi| ppi != MINIMUM_SCANNING_RESOLUTION_PPI),
+            width: image.width as u16,
+            height: image.height as u16,
+            compression: WSQ,
+            data,
+        })
+    }
+
+    /// Replace the image with `image` compressed to WSQ at `bitrate`.
+    pub fn set_image(&mut self, image: &GrayImage, bitrate: f64) -> io::Result<()> {
+        let record = Self::from_image(image, self.idc, bitrate)?;
+        *self = Self { impression_type: self.impression_type, finger_positions: self.finger_positions, ..record };
+        Ok(())
+    }
+
+    /// The image of the record, decompressed when it is WSQ.
+    pub fn to_image(&self) -> io::Result<GrayImage> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut image = match self.compression {
+            UNCOMPRESSED => GrayImage::new(width, height, self.data.clone())
+                .map_err(|e| invalid_data(e.to_string()))?,
+            WSQ => codec::decode(&self.data)?,
+            gca => return Err(io::Error::new(io::ErrorKind::Unsupported, format!("Compression algorithm {} is not supported", gca))),
+        };
+        if (image.width, image.height) != (width, height) {
+            return Err(invalid_data(format!("Record is {}x{} but its image is {}x{}", width, height, image.width, image.height)));
+        }
+        if !self.native_resolution {
+            image.ppi = Some(MINIMUM_SCANNING_RESOLUTION_PPI);
+        }
+        Ok(image)
+    }
+
+    /// Length of the encoded record, the LEN field.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.data.len()
+    }
+
+    /// Parse the record at the start of `contents`, bytes after its length are ignored.
+    pub fn parse(contents: &[u8]) -> io::Result<Self> {
+        let header = contents.get(..HEADER_LEN)
+            .ok_or_else(|| invalid_data(format!("Type-4 record needs {} header bytes, found {}", HEADER_LEN, contents.len())))?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if len < HEADER_LEN || len > contents.len() {
+            return Err(invalid_data(format!("Type-4 record length {} out of range", len)));
+        }
+        let isr = header[12];
+        if isr > 1 {
+            return Err(invalid_data(format!("Invalid image scanning resolution {}", isr)));
+        }
+        let mut finger_positions = [0; 6];
+        finger_positions.copy_from_slice(&header[6..12]);
+        Ok(Self {
+            idc: header[4],
+            impression_type: header[5],
+            finger_positions,
+            native_resolution: isr == 1,
+            width: u16::from_be_bytes([header[13], header[14]]),
+            height: u16::from_be_bytes([header[15], header[16]]),
+            compression: header[17],
+            data: contents[HEADER_LEN..len].to_vec(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut contents = Vec::with_capacity(self.encoded_len());
+        contents.extend_from_slice(&(self.encoded_len() as u32).to_be_bytes());
+        contents.extend([self.idc, self.impression_type]);
+        contents.extend_from_slice(&self.finger_positions);
+        contents.push(self.native_resolution as u8);
+        contents.extend_from_slice(&self.width.to_be_bytes());
+        contents.extend_from_slice(&self.height.to_be_bytes());
+        contents.push(self.compression);
+        contents.extend_from_slice(&self.data);
+        contents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Type4Record, UNCOMPRESSED, WSQ};
+    use crate::codec::tests::fingerprint;
+    use crate::gray::GrayImage;
+
+    #[test]
+    fn test_header() {
+        let record = Type4Record {
+            idc: 3,
+            impression_type: 1,
+            finger_positions: [2, 255, 255, 255, 255, 255],
+            native_resolution: false,
+            width: 2,
+            height: 2,
+            compression: UNCOMPRESSED,
+            data: vec![10, 20, 30, 40],
+        };
+        let contents = record.encode();
+        assert_eq!([0, 0, 0, 22, 3, 1, 2, 255, 255, 255, 255, 255, 0, 0, 2, 0, 2, 0], contents[..18]);
+        let mut trailing = contents.clone();
+        trailing.extend([0xAB; 5]);
+        assert_eq!(record, Type4Record::parse(&trailing).unwrap());
+        let image = record.to_image().unwrap();
+        assert_eq!((vec![10, 20, 30, 40], Some(500)), (image.data, image.ppi));
+        assert!(Type4Record::parse(&contents[..21]).is_err());
+    }
+
+    #[test]
+    fn test_wsq_record() {
+        let image = fingerprint(400, 420);
+        let record = Type4Record::from_image(&image, 1, 0.75).unwrap();
+        assert_eq!((400, 420, WSQ, false), (record.width, record.height, record.compression, record.native_resolution));
+        let parsed = Type4Record::parse(&record.encode()).unwrap();
+        let decoded = parsed.to_image().unwrap();
+        assert_eq!((400, 420), (decoded.width, decoded.height));
+
+        let mut wrong_size = parsed.clone();
+        wrong_size.width = 399;
+        assert!(wrong_size.to_image().is_err());
+        let native = Type4Record::from_image(&GrayImage { ppi: Some(1000), ..image }, 1, 0.75).unwrap();
+        assert!(native.native_resolution);
+    }
+}
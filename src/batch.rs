@@ -0,0 +1,123 @@
+//! Converting every file of a directory tree, for the `--recursive` commands
+use std::io;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+use rayon::prelude::*;
+
+/// Sizes of one converted image
+pub struct Conversion {
+    pub pixels: usize,
+    pub compressed_bytes: usize,
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub converted: usize,
+    pub failed: usize,
+    pixels: usize,
+    compressed_bytes: usize,
+    ratios: Vec<f64>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} converted, {} failed", self.converted, self.failed)?;
+        if self.ratios.is_empty() {
+            return Ok(());
+        }
+        let min = self.ratios.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = self.ratios.iter().copied().fold(0., f64::max);
+        let mean = self.ratios.iter().sum::<f64>() / self.ratios.len() as f64;
+        write!(f, "\n{} pixels in {} compressed bytes, {:.2} bits per pixel", self.pixels, self.compressed_bytes,
+               self.compressed_bytes as f64 * 8. / self.pixels as f64)?;
+        write!(f, "\ncompression ratio {:.1}:1 overall, {:.1}:1 min, {:.1}:1 mean, {:.1}:1 max",
+               self.pixels as f64 / self.compressed_bytes as f64, min, mean, max)
+    }
+}
+
+/// The files below `directory` accepted by `select`, in order.
+fn files(directory: &Path, select: &dyn Fn(&Path) -> bool, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(directory)?.map(|entry| entry.map(|e| e.path())).collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            files(&path, select, found)?;
+        } else if select(&path) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Convert the files below `input` accepted by `select` to files with `extension` at the same
+/// place below `output`, on `jobs` threads, all available ones for 0. A file that fails is
+/// reported with its path and skipped.
+pub fn convert<F>(input: &Path, output: &Path, select: &dyn Fn(&Path) -> bool, extension: &str, jobs: usize, convert: F)
+    -> io::Result<Summary>
+where
+    F: Fn(&Path, &Path) -> io::Result<Conversion> + Sync,
+{
+    let mut inputs = vec![];
+    files(input, select, &mut inputs)?;
+    let convert_to_target = |path: &PathBuf| {
+        let target = output.join(path.strip_prefix(input).unwrap_or(path)).with_extension(extension);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        convert(path, &target)
+    };
+    let convert_file = |path: &PathBuf| convert_to_target(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)));
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build().map_err(io::Error::other)?;
+    let results = pool.install(|| inputs.par_iter().map(convert_file).collect::<Vec<_>>());
+
+    let mut summary = Summary::default();
+    for (path, result) in inputs.iter().zip(results) {
+        match result {
+            Ok(conversion) => {
+                summary.converted += 1;
+                summary.pixels += conversion.pixels;
+                summary.compressed_bytes += conversion.compressed_bytes;
+                summary.ratios.push(conversion.pixels as f64 / conversion.compressed_bytes as f64);
+                log::info!("{}: compression ratio {:.1}:1", path.display(), conversion.pixels as f64 / conversion.compressed_bytes as f64);
+            },
+            Err(e) => {
+                summary.failed += 1;
+                eprintln!("error: {}", e);
+            },
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    use super::{convert, Conversion};
+
+    #[test]
+    fn test_convert() {
+        let directory = std::env::temp_dir().join(format!("wsq-batch-{}", std::process::id()));
+        let (input, output) = (directory.join("in"), directory.join("out"));
+        fs::create_dir_all(input.join("nested")).unwrap();
+        for (name, contents) in [("a.pgm", "4"), ("nested/b.pgm", "bad"), ("nested/c.pgm", "8"), ("d.pgm", ""), ("e.txt", "1")] {
+            fs::write(input.join(name), contents).unwrap();
+        }
+        let is_pgm = |path: &Path| path.extension().is_some_and(|e| e == "pgm");
+        let summary = convert(&input, &output, &is_pgm, "wsq", 2, |from, to| {
+            let pixels = fs::read_to_string(from)?.parse::<usize>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(to, "1")?;
+            Ok(Conversion { pixels, compressed_bytes: 1 })
+        });
+        let converted = [output.join("a.wsq").exists(), output.join("nested/b.wsq").exists(), output.join("nested/c.wsq").exists()];
+        fs::remove_dir_all(&directory).unwrap();
+        let summary = summary.unwrap();
+        assert_eq!((2, 2), (summary.converted, summary.failed));
+        assert_eq!([true, false, true], converted);
+        assert!(summary.to_string().starts_with("2 converted, 2 failed\n12 pixels in 2 compressed bytes"));
+    }
+}
@@ -0,0 +1,326 @@
+//! Module for checking the encoder and decoder against reference data, Annex AA
+//
+// Encoder compliance (AA.2): compressing an original image must give
+//     a file within 0.4% of the size of the reference file, comments excluded,
+//     bin widths Q and zero bin widths Z of subbands 0 to 59 within 0.051% of the reference ones,
+//     at least 99.99% of the bin indices equal to the reference ones and none off by more than 1.
+// Decoder compliance (AA.3): decompressing the reference file must give at least 99.9% of the
+// pixels of the reference reconstruction and none off by more than 1.
+//
+// A directory of test data holds each original image as `<name>.pgm`, or as `<name>.raw` with its
+// size in the NIST comment file `<name>.ncm` as written by NBIS, the reference compressed file
+// `<name>.wsq` and optionally the reference reconstruction `<name>.rec.pgm` or `<name>.rec.raw`.
+// Each original is compressed at the bitrate the NIST comment of its reference file records.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::codec;
+use crate::codestream::{Codestream, Parser, Segment};
+use crate::gray::GrayImage;
+use crate::swt::layout::NUM_QUANTIZED_SUBBANDS;
+use crate::{pnm, raw};
+
+/// Largest relative difference of the compressed file sizes
+pub const FILE_SIZE_TOLERANCE: f64 = 0.004;
+/// Largest relative difference of the bin widths
+pub const BIN_WIDTH_TOLERANCE: f64 = 0.00051;
+/// Smallest share of bin indices equal to the reference ones
+pub const MIN_IDENTICAL_INDICES: f64 = 0.9999;
+/// Smallest share of reconstructed pixels equal to the reference ones
+pub const MIN_IDENTICAL_PIXELS: f64 = 0.999;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Bytes of compressed data without its comment segments.
+fn size_without_comments(contents: &[u8]) -> io::Result<usize> {
+    let mut comments = 0;
+    for segment in Parser::new(contents) {
+        if let (_, Segment::Comment(text)) = segment? {
+            comments += 4 + text.len();
+        }
+    }
+    Ok(contents.len() - comments)
+}
+
+fn relative_difference(test: f64, reference: f64) -> f64 {
+    if test == reference {
+        0.
+    } else if reference == 0. {
+        f64::INFINITY
+    } else {
+        ((test - reference) / reference).abs()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderCheck {
+    /// Relative difference of the file sizes, comments excluded
+    pub file_size_difference: f64,
+    /// Largest relative difference of the bin widths and zero bin widths
+    pub bin_width_difference: f64,
+    /// Share of bin indices equal to the reference ones, 0 when the transmitted subbands differ
+    pub identical_indices: f64,
+    pub max_index_difference: u32,
+}
+
+impl EncoderCheck {
+    pub fn passed(&self) -> bool {
+        self.file_size_difference <= FILE_SIZE_TOLERANCE
+            && self.bin_width_difference <= BIN_WIDTH_TOLERANCE
+            && self.identical_indices >= MIN_IDENTICAL_INDICES
+            && self.max_index_difference <= 1
+    }
+}
+
+impl fmt::Display for EncoderCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "encoder {}: file size {:.3}%, bin widths {:.4}%, {:.3}% identical bin indices, largest difference {}",
+               if self.passed() { "PASS" } else { "FAIL" }, self.file_size_difference * 100., self.bin_width_difference * 100.,
+               self.identical_indices * 100., self.max_index_difference)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecoderCheck {
+    pub identical_pixels: f64,
+    pub max_pixel_difference: u8,
+}
+
+impl DecoderCheck {
+    pub fn passed(&self) -> bool {
+        self.identical_pixels >= MIN_IDENTICAL_PIXELS && self.max_pixel_difference <= 1
+    }
+}
+
+impl fmt::Display for DecoderCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decoder {}: {:.3}% identical pixels, largest difference {}",
+               if self.passed() { "PASS" } else { "FAIL" }, self.identical_pixels * 100., self.max_pixel_difference)
+    }
+}
+
+/// Compress `original` at `bitrate` and compare the result with the reference compressed file.
+pub fn check_encoder(original: &GrayImage, reference: &[u8], bitrate: f64) -> io::Result<EncoderCheck> {
+    let test = codec::encode(original, bitrate)?;
+    let (test_codestream, reference_codestream) = (Codestream::parse(&test)?, Codestream::parse(reference)?);
+    let (test_size, reference_size) = (size_without_comments(&test)?, size_without_comments(reference)?);
+
+    let (test_table, reference_table) = (&test_codestream.quantization_table, &reference_codestream.quantization_table);
+    let bin_width_difference = (0..NUM_QUANTIZED_SUBBANDS)
+        .flat_map(|k| {
+            let get = |widths: &[f64]| widths.get(k).copied().unwrap_or(0.);
+            [relative_difference(get(&test_table.bin_widths), get(&reference_table.bin_widths)),
+             relative_difference(get(&test_table.zero_bin_widths), get(&reference_table.zero_bin_widths))]
+        })
+        .fold(0., f64::max);
+
+    let test_indices = codec::decode_coefficients(&test_codestream)?;
+    let reference_indices = codec::decode_coefficients(&reference_codestream)?;
+    let same_subbands = (0..NUM_QUANTIZED_SUBBANDS).all(|k| test_table.is_quantized(k) == reference_table.is_quantized(k));
+    let (identical_indices, max_index_difference) = if same_subbands && test_indices.len() == reference_indices.len() {
+        let identical = test_indices.iter().zip(&reference_indices).filter(|(t, r)| t == r).count();
+        let max = test_indices.iter().zip(&reference_indices).map(|(t, r)| t.abs_diff(*r)).max().unwrap_or(0);
+        (if test_indices.is_empty() { 1. } else { identical as f64 / test_indices.len() as f64 }, max)
+    } else {
+        (0., u32::MAX)
+    };
+
+    Ok(EncoderCheck {
+        file_size_difference: relative_difference(test_size as f64, reference_size as f64),
+        bin_width_difference,
+        identical_indices,
+        max_index_difference,
+    })
+}
+
+/// Decompress the reference compressed file and compare the result with the reference reconstruction.
+pub fn check_decoder(reference: &[u8], reconstruction: &GrayImage) -> io::Result<DecoderCheck> {
+    let test = codec::decode(reference)?;
+    if (test.width, test.height) != (reconstruction.width, reconstruction.height) {
+        return Err(invalid_data(format!("Decoded a {}x{} image but the reference reconstruction is {}x{}",
+                                        test.width, test.height, reconstruction.width, reconstruction.height)));
+    }
+    let identical = test.data.iter().zip(&reconstruction.data).filter(|(t, r)| t == r).count();
+    Ok(DecoderCheck {
+        identical_pixels: identical as f64 / test.data.len().max(1) as f64,
+        max_pixel_difference: test.data.iter().zip(&reconstruction.data).map(|(t, r)| t.abs_diff(*r)).max().unwrap_or(0),
+    })
+}
+
+/// The result of one image of a test data directory.
+#[derive(Debug)]
+pub struct ImageReport {
+    pub name: String,
+    /// The bitrate the original was compressed at
+    pub bitrate: f64,
+    pub encoder: io::Result<EncoderCheck>,
+    /// `None` without a reference reconstruction
+    pub decoder: Option<io::Result<DecoderCheck>>,
+}
+
+impl ImageReport {
+    pub fn passed(&self) -> bool {
+        self.encoder.as_ref().is_ok_and(EncoderCheck::passed)
+            && self.decoder.as_ref().is_none_or(|decoder| decoder.as_ref().is_ok_and(DecoderCheck::passed))
+    }
+}
+
+impl fmt::Display for ImageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {} at {} bits per pixel", self.name, if self.passed() { "PASS" } else { "FAIL" }, self.bitrate)?;
+        match &self.encoder {
+            Ok(check) => writeln!(f, "  {}", check)?,
+            Err(e) => writeln!(f, "  encoder FAIL: {}", e)?,
+        }
+        match &self.decoder {
+            Some(Ok(check)) => write!(f, "  {}", check),
+            Some(Err(e)) => write!(f, "  decoder FAIL: {}", e),
+            None => write!(f, "  decoder not checked, no reference reconstruction"),
+        }
+    }
+}
+
+/// The width and height in a NIST comment file.
+fn size_from_nist_comment(path: &Path) -> io::Result<(usize, usize)> {
+    let text = fs::read_to_string(path)?;
+    let value = |key: &str| text.lines()
+        .find_map(|line| line.strip_prefix(key).and_then(|v| v.trim().parse::<usize>().ok()))
+        .ok_or_else(|| invalid_data(format!("{} has no {}", path.display(), key.trim())));
+    Ok((value("PIX_WIDTH ")?, value("PIX_HEIGHT ")?))
+}
+
+/// `stem` with `extension` appended, unlike `Path::with_extension` keeping any extension of `stem`.
+fn with_extension(stem: &Path, extension: &str) -> PathBuf {
+    let mut path = stem.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// An image saved as `<stem>.pgm`, or as `<stem>.raw` of `size` or with a NIST comment file.
+fn read_image(stem: &Path, size: Option<(usize, usize)>) -> io::Result<Option<GrayImage>> {
+    let pgm = with_extension(stem, "pgm");
+    if pgm.exists() {
+        return Ok(Some(GrayImage::from(&pnm::parse(&fs::read(pgm)?)?)));
+    }
+    let path = with_extension(stem, "raw");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let (width, height) = match size {
+        Some(size) => size,
+        None => size_from_nist_comment(&with_extension(stem, "ncm"))?,
+    };
+    raw::parse(&fs::read(path)?, width, height).map(Some)
+}
+
+/// The bitrate in the NIST comment of a compressed file, if any.
+fn bitrate_from_nist_comment(contents: &[u8]) -> Option<f64> {
+    let comments = Parser::new(contents)
+        .map_while(Result::ok)
+        .filter_map(|(_, segment)| match segment {
+            Segment::Comment(text) => Some(text),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    codec::bitrate_from_comments(&comments)
+}
+
+fn check_image(stem: &Path, default_bitrate: f64) -> ImageReport {
+    let name = stem.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let reference = fs::read(with_extension(stem, "wsq"));
+    let reference = || reference.as_deref().map_err(|e| io::Error::new(e.kind(), e.to_string()));
+    let bitrate = reference().ok().and_then(bitrate_from_nist_comment).unwrap_or(default_bitrate);
+    let original = read_image(stem, None)
+        .and_then(|image| image.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No original image")));
+    let encoder = original.as_ref().map_err(|e| io::Error::new(e.kind(), e.to_string()))
+        .and_then(|original| check_encoder(original, reference()?, bitrate));
+    let size = original.as_ref().ok().map(|original| (original.width, original.height));
+    let decoder = read_image(&with_extension(stem, "rec"), size).transpose()
+        .map(|reconstruction| check_decoder(reference()?, &reconstruction?));
+    ImageReport { name, bitrate, encoder, decoder }
+}
+
+/// Check every image of a test data directory that has a reference compressed file, in name order.
+/// References without a bitrate in their NIST comment are checked at `default_bitrate`.
+pub fn check_directory(directory: &Path, default_bitrate: f64) -> io::Result<Vec<ImageReport>> {
+    let mut stems = fs::read_dir(directory)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|e| e.eq_ignore_ascii_case("wsq")))
+        .map(|path| path.with_extension(""))
+        .collect::<Vec<_>>();
+    stems.sort();
+    Ok(stems.iter().map(|stem| check_image(stem, default_bitrate)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{check_decoder, check_directory, check_encoder};
+    use crate::codec::tests::fingerprint;
+    use crate::codec::{decode, encode, DEFAULT_BITRATE};
+    use crate::codestream::Codestream;
+    use crate::pnm;
+
+    #[test]
+    fn test_own_reference() {
+        let image = fingerprint(256, 272);
+        let reference = encode(&image, DEFAULT_BITRATE).unwrap();
+        let encoder = check_encoder(&image, &reference, DEFAULT_BITRATE).unwrap();
+        assert_eq!((0., 0., 1., 0), (encoder.file_size_difference, encoder.bin_width_difference,
+                                     encoder.identical_indices, encoder.max_index_difference));
+        assert!(encoder.passed());
+        let other_bitrate = check_encoder(&image, &reference, 1.).unwrap();
+        assert!(!other_bitrate.passed() && other_bitrate.file_size_difference > 0.1);
+
+        let mut reconstruction = decode(&reference).unwrap();
+        assert!(check_decoder(&reference, &reconstruction).unwrap().passed());
+        reconstruction.data[0] = reconstruction.data[0].wrapping_add(2);
+        let decoder = check_decoder(&reference, &reconstruction).unwrap();
+        assert_eq!(2, decoder.max_pixel_difference);
+        assert!(!decoder.passed());
+    }
+
+    #[test]
+    fn test_directory() {
+        let directory = std::env::temp_dir().join(format!("wsq-certification-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let image = fingerprint(200, 208);
+        let reference = encode(&image, DEFAULT_BITRATE).unwrap();
+        fs::write(directory.join("a.raw"), &image.data).unwrap();
+        fs::write(directory.join("a.ncm"), "NIST_COM 5\nPIX_WIDTH 200\nPIX_HEIGHT 208\nPIX_DEPTH 8\n").unwrap();
+        fs::write(directory.join("a.wsq"), &reference).unwrap();
+        fs::write(directory.join("a.rec.raw"), decode(&reference).unwrap().data).unwrap();
+        fs::write(directory.join("b.pgm"), pnm::encode(&pnm::Image::from(&image), pnm::Format::Raw)).unwrap();
+        // compressed at another bitrate than the comment records
+        let mut other = Codestream::parse(&encode(&image, 2.).unwrap()).unwrap();
+        other.comments = vec![b"NIST_COM 2\nWSQ_BITRATE 1.000000".to_vec()];
+        fs::write(directory.join("b.wsq"), other.encode()).unwrap();
+        fs::write(directory.join("c.wsq"), &reference).unwrap();
+        // without a bitrate in its comment
+        let mut unrecorded = Codestream::parse(&encode(&image, 2.).unwrap()).unwrap();
+        unrecorded.comments.clear();
+        fs::write(directory.join("d.pgm"), pnm::encode(&pnm::Image::from(&image), pnm::Format::Raw)).unwrap();
+        fs::write(directory.join("d.wsq"), unrecorded.encode()).unwrap();
+
+        let reports = check_directory(&directory, 2.);
+        fs::remove_dir_all(&directory).unwrap();
+        let reports = reports.unwrap();
+        assert_eq!(vec!["a", "b", "c", "d"], reports.iter().map(|r| r.name.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec![DEFAULT_BITRATE, 1., DEFAULT_BITRATE, 2.], reports.iter().map(|r| r.bitrate).collect::<Vec<_>>());
+        assert!(reports[0].passed());
+        assert!(reports[0].decoder.is_some());
+        assert!(!reports[1].passed());
+        assert!(reports[1].decoder.is_none());
+        assert!(reports[2].encoder.is_err() && !reports[2].passed());
+        assert!(reports[3].passed());
+        assert!(reports[0].to_string().starts_with("a: PASS at 0.75 bits per pixel\n  encoder PASS: file size 0.000%"));
+    }
+}
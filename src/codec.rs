@@ -0,0 +1,571 @@
+//! Module for compressing grey scale images to WSQ and back
+//
+// The encoder normalizes the pixels, decomposes them into the 64 subbands, allocates the bits of
+// the target bitrate over the subbands and Huffman codes the quantized coefficients in three
+// blocks: subbands 0 to 18 with one table, subbands 19 to 51 and 52 to 59 with a second one, or
+// with a table for every block on request. The decoder follows the tables found in the compressed
+// data, so it reads the output of any encoder.
+use std::io;
+
+use crate::codestream::{is_transmittable, transmitted, Block, Codestream, FrameHeader, TransformTable, ENCODER_NUMBER_TWO};
+use crate::entropy::{decoder, encoder, CompressedImageData, TableSharing, MAX_HUFFMAN_TABLES};
+use crate::gray::GrayImage;
+use crate::quantization::{dequantizer, quantizer, subband_variances, QuantizationTable};
+use crate::swt::layout::{SubbandLayout, NUM_QUANTIZED_SUBBANDS, NUM_SUBBANDS};
+use crate::swt::{FloatImage, Sample, TwoChannelSubbandCoder};
+
+/// The bitrate, in bits per pixel, the FBI uses for ten-print cards
+pub const DEFAULT_BITRATE: f64 = 0.75;
+/// The first subband after each block
+pub const BLOCK_ENDS: [usize; 3] = [19, 52, NUM_QUANTIZED_SUBBANDS];
+/// Bisection steps searching the allocation that meets the bitrate of `requantize`
+const REQUANTIZE_STEPS: usize = 8;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The NIST comment NBIS writes after SOI, recording the image attributes and the target bitrate
+/// if there is one.
+fn nist_comment(image: &GrayImage, bitrate: Option<f64>) -> Vec<u8> {
+    let ppi = image.ppi.map_or(-1, |ppi| ppi as i32);
+    let mut comment = format!("NIST_COM {}\nPIX_WIDTH {}\nPIX_HEIGHT {}\nPIX_DEPTH 8\nPPI {}\nLOSSY 1\nCOLORSPACE GRAY\nCOMPRESSION WSQ",
+                              if bitrate.is_some() { 9 } else { 8 }, image.width, image.height, ppi);
+    if let Some(bitrate) = bitrate {
+        comment += &format!("\nWSQ_BITRATE {:.6}", bitrate);
+    }
+    comment.into_bytes()
+}
+
+/// The value of `key` in a NIST comment, if any.
+fn nist_comment_value<T: std::str::FromStr>(comments: &[Vec<u8>], key: &str) -> Option<T> {
+    comments.iter()
+        .filter(|c| c.starts_with(b"NIST_COM"))
+        .flat_map(|c| String::from_utf8_lossy(c).lines().map(str::to_string).collect::<Vec<_>>())
+        .find_map(|line| line.strip_prefix(key).and_then(|value| value.trim().parse().ok()))
+}
+
+/// The resolution recorded in a NIST comment, if any.
+pub fn ppi_from_comments(comments: &[Vec<u8>]) -> Option<u16> {
+    nist_comment_value(comments, "PPI ")
+}
+
+/// The bitrate a NIST comment records the image was compressed at, if any.
+pub fn bitrate_from_comments(comments: &[Vec<u8>]) -> Option<f64> {
+    nist_comment_value(comments, "WSQ_BITRATE ")
+}
+
+/// What the encoder did with one of the quantized subbands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubbandStatistics {
+    pub index: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Variance the bit allocation used
+    pub variance: f64,
+    pub bin_width: f64,
+    pub zero_bin_width: f64,
+    /// Quantized coefficients other than zero
+    pub nonzero: usize,
+    /// Bits of the Huffman codes and raw bits of the symbols starting in the subband
+    pub bits: usize,
+}
+
+impl SubbandStatistics {
+    /// Whether the subband got no bits and is not transmitted.
+    pub fn is_discarded(&self) -> bool {
+        self.bin_width == 0.
+    }
+}
+
+/// Compress `image` at close to `bitrate` bits per pixel.
+pub fn encode(image: &GrayImage, bitrate: f64) -> io::Result<Vec<u8>> {
+    Ok(encode_codestream(image, bitrate)?.encode())
+}
+
+pub fn encode_codestream(image: &GrayImage, bitrate: f64) -> io::Result<Codestream> {
+    encode_with_statistics(image, bitrate).map(|(codestream, _)| codestream)
+}
+
+/// Compress `image` like `encode_codestream`, with the blocks sharing Huffman tables as `sharing` says.
+pub fn encode_codestream_with_sharing(image: &GrayImage, bitrate: f64, sharing: TableSharing) -> io::Result<Codestream> {
+    let choose = |variances: &[f64], layout: &SubbandLayout| QuantizationTable::for_bitrate(variances, layout, bitrate);
+    encode_quantized(image, Some(bitrate), sharing, choose).map(|(codestream, _)| codestream)
+}
+
+/// Compress `image` like `encode_codestream`, also returning the statistics of subbands 0 to 59.
+pub fn encode_with_statistics(image: &GrayImage, bitrate: f64) -> io::Result<(Codestream, Vec<SubbandStatistics>)> {
+    let choose = |variances: &[f64], layout: &SubbandLayout| QuantizationTable::for_bitrate(variances, layout, bitrate);
+    encode_quantized(image, Some(bitrate), TableSharing::Shared, choose)
+}
+
+/// Compress `image` with the bin widths, zero bin widths and bin center of `table`, e.g. the
+/// table of another file, instead of ones for a bitrate. Subbands with a bin width of 0 and
+/// subbands 60 to 63 are not transmitted. The values are rounded to the precision of the
+/// compressed data, the statistics of subbands 0 to 59 are returned as well. Values the compressed
+/// data can not hold and bin widths too fine for 16-bit bin indices are rejected.
+pub fn encode_with_table(image: &GrayImage, table: &QuantizationTable) -> io::Result<(Codestream, Vec<SubbandStatistics>)> {
+    check_transmittable(table)?;
+    if table.len() > NUM_SUBBANDS || table.zero_bin_widths.len() != table.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "Quantization table with {} bin widths and {} zero bin widths", table.len(), table.zero_bin_widths.len())));
+    }
+    let padded = |widths: &[f64]| (0..NUM_SUBBANDS)
+        .map(|k| if k < NUM_QUANTIZED_SUBBANDS { widths.get(k).copied().unwrap_or(0.) } else { 0. })
+        .collect();
+    let table = QuantizationTable::new(transmitted(table.bin_center), padded(&table.bin_widths), padded(&table.zero_bin_widths));
+    encode_quantized(image, None, TableSharing::Shared, |_, _| table)
+}
+
+/// Fails for bin widths, zero bin widths or a bin center the quantization table segment can not hold.
+fn check_transmittable(table: &QuantizationTable) -> io::Result<()> {
+    let mut values = table.bin_widths.iter().chain(&table.zero_bin_widths).chain([&table.bin_center]);
+    match values.find(|v| !is_transmittable(**v)) {
+        Some(value) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid quantization parameter {}", value))),
+        None => Ok(()),
+    }
+}
+
+/// Compress `image` with the quantization table `choose` makes of the subband variances.
+fn encode_quantized<T>(image: &GrayImage, bitrate: Option<f64>, sharing: TableSharing, choose: T)
+    -> io::Result<(Codestream, Vec<SubbandStatistics>)>
+where
+    T: FnOnce(&[f64], &SubbandLayout) -> QuantizationTable,
+{
+    if image.width == 0 || image.height == 0 || image.width > u16::MAX as usize || image.height > u16::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("Cannot encode a {}x{} image", image.width, image.height)));
+    }
+    let mut pixels = image.to_float_image::<f64>();
+    let (mean, rescale) = pixels.get_mean_and_rescale();
+    // normalize with the values the decoder reads back
+    let mean = transmitted(mean);
+    let rescale = if rescale > 0. { transmitted(rescale) } else { 1. };
+    pixels.normalize(mean, rescale);
+
+    let transform_table = TransformTable::default();
+    let (lowpass, highpass) = transform_table.filters();
+    TwoChannelSubbandCoder::new(lowpass, highpass)?.decompose(&mut pixels);
+
+    let layout = SubbandLayout::new(image.width, image.height);
+    let variances = subband_variances(&pixels, &layout);
+    let table = choose(&variances, &layout);
+    check_transmittable(&table)?;
+    let quantization_table = QuantizationTable::new(
+        table.bin_center,
+        table.bin_widths.iter().map(|q| transmitted(*q)).collect(),
+        table.zero_bin_widths.iter().map(|z| transmitted(*z)).collect(),
+    );
+
+    let table_ids = sharing.table_ids(BLOCK_ENDS.len());
+    let (blocks, statistics) = code_subbands(&pixels, &layout, &quantization_table, &variances, table_ids)?;
+
+    let codestream = Codestream {
+        frame_header: FrameHeader {
+            black: 0,
+            white: 255,
+            height: image.height as u16,
+            width: image.width as u16,
+            mean,
+            rescale,
+            encoder: ENCODER_NUMBER_TWO,
+            software: 0,
+        },
+        transform_table,
+        quantization_table,
+        restart_interval: 0,
+        comments: vec![nist_comment(image, bitrate)],
+        blocks,
+    };
+    Ok((codestream, statistics))
+}
+
+/// Quantize the subbands of the decomposed `pixels` with `table` and Huffman code them in the three
+/// blocks under the Huffman table identifiers `table_ids`, with the statistics of every quantized
+/// subband. Fails for bin indices beyond the 16 bits a coefficient is coded in.
+fn code_subbands(pixels: &FloatImage<f64>, layout: &SubbandLayout, table: &QuantizationTable, variances: &[f64],
+                 table_ids: Vec<u8>) -> io::Result<(Vec<Block>, Vec<SubbandStatistics>)>
+{
+    let mut statistics = layout.quantized()
+        .map(|s| SubbandStatistics {
+            index: s.index,
+            width: s.width,
+            height: s.height,
+            variance: variances[s.index],
+            bin_width: table.bin_widths[s.index],
+            zero_bin_width: table.zero_bin_widths[s.index],
+            nonzero: 0,
+            bits: 0,
+        })
+        .collect::<Vec<_>>();
+    let mut block_coefficients = vec![vec![]; BLOCK_ENDS.len()];
+    // the transmitted subbands of every block with the end of their coefficients
+    let mut block_subbands = vec![vec![]; BLOCK_ENDS.len()];
+    for subband in layout.quantized().filter(|s| table.is_quantized(s.index)) {
+        let block = BLOCK_ENDS.iter().position(|end| subband.index < *end).unwrap_or(BLOCK_ENDS.len() - 1);
+        let k = subband.index;
+        let coefficients = quantizer::quantize_subband(
+            &subband.crop(pixels).data, table.bin_widths[k], table.zero_bin_widths[k]);
+        if let Some(index) = coefficients.iter().find(|p| p.unsigned_abs() > u16::MAX as u32) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "Bin index {} of subband {} does not fit in 16 bits, the bin width {} is too small", index, k, table.bin_widths[k])));
+        }
+        statistics[k].nonzero = coefficients.iter().filter(|p| **p != 0).count();
+        block_coefficients[block].extend(coefficients);
+        block_subbands[block].push((k, block_coefficients[block].len()));
+    }
+    let image_data = CompressedImageData::with_table_ids(block_coefficients, table_ids);
+    for ((coefficients, subbands), huffman_table) in image_data.blocks.iter().zip(&block_subbands).zip(&image_data.tables) {
+        let ends = subbands.iter().map(|(_, end)| *end).collect::<Vec<_>>();
+        for ((k, _), bits) in subbands.iter().zip(encoder::bits_per_range(coefficients, huffman_table, &ends)) {
+            statistics[*k].bits = bits;
+        }
+    }
+    let blocks = image_data.encode().into_iter().zip(image_data.table_ids).zip(image_data.tables)
+        .map(|((data, huffman_table_id), huffman_table)| Block { huffman_table_id, huffman_table, data: vec![data] })
+        .collect();
+    Ok((blocks, statistics))
+}
+
+pub fn decode(contents: &[u8]) -> io::Result<GrayImage> {
+    decode_with::<f64>(contents)
+}
+
+/// Decode with the transform and the de-quantization computed in the sample type `F`.
+pub fn decode_with<F: Sample>(contents: &[u8]) -> io::Result<GrayImage> {
+    decode_codestream::<F>(&Codestream::parse(contents)?)
+}
+
+/// The quantized coefficients of every block with its Huffman table and table identifier.
+pub fn decode_image_data(codestream: &Codestream) -> io::Result<CompressedImageData> {
+    let mut blocks = vec![];
+    for block in &codestream.blocks {
+        let mut coefficients = vec![];
+        for data in &block.data {
+            decoder::decode(data, &block.huffman_table, &mut coefficients)?;
+        }
+        blocks.push(coefficients);
+    }
+    Ok(CompressedImageData {
+        blocks,
+        table_ids: codestream.blocks.iter().map(|b| b.huffman_table_id).collect(),
+        tables: codestream.blocks.iter().map(|b| b.huffman_table.clone()).collect(),
+    })
+}
+
+/// The quantized coefficients of all transmitted subbands, in order.
+pub fn decode_coefficients(codestream: &Codestream) -> io::Result<Vec<i32>> {
+    Ok(decode_image_data(codestream)?.coefficients())
+}
+
+/// The dequantized subbands of `codestream` in the layout of the decomposition, without the
+/// inverse transform.
+pub fn dequantize_coefficients<F: Sample>(codestream: &Codestream) -> io::Result<FloatImage<F>> {
+    let header = &codestream.frame_header;
+    let (width, height) = (header.width as usize, header.height as usize);
+    if width == 0 || height == 0 {
+        return Err(invalid_data(format!("Invalid image size {}x{}", width, height)));
+    }
+    let table = &codestream.quantization_table;
+    let layout = SubbandLayout::new(width, height);
+    let coefficients = decode_coefficients(codestream)?;
+    let expected = layout.iter().filter(|s| table.is_quantized(s.index)).map(|s| s.len()).sum::<usize>();
+    if coefficients.len() != expected {
+        return Err(invalid_data(format!("Expected {} coefficients, the blocks hold {}", expected, coefficients.len())));
+    }
+
+    let mut image = FloatImage::<F>::zeros(width, height);
+    let mut remaining = &coefficients[..];
+    for subband in layout.iter().filter(|s| table.is_quantized(s.index)) {
+        let (indices, rest) = remaining.split_at(subband.len());
+        remaining = rest;
+        let k = subband.index;
+        let data = dequantizer::dequantize_subband(indices, table.bin_widths[k], table.zero_bin_widths[k], table.bin_center);
+        let band = FloatImage { data, width: subband.width, height: subband.height, min_value: F::default(), max_value: F::default() };
+        image.paste(subband.x, subband.y, &band);
+    }
+    Ok(image)
+}
+
+pub fn decode_codestream<F: Sample>(codestream: &Codestream) -> io::Result<GrayImage> {
+    let mut image = dequantize_coefficients::<F>(codestream)?;
+    let (lowpass, highpass) = codestream.transform_table.filters();
+    TwoChannelSubbandCoder::new(lowpass, highpass)?.reconstruct(&mut image);
+    let header = &codestream.frame_header;
+    image.denormalize(header.mean, header.rescale);
+    let mut decoded = GrayImage::from_float_image(&image);
+    decoded.ppi = ppi_from_comments(&codestream.comments);
+    Ok(decoded)
+}
+
+/// Compress `codestream` again at close to `bitrate` bits per pixel without transforming it back
+/// to pixels: its dequantized coefficients are quantized with the bin widths for at most `bitrate`,
+/// but never finer ones than those of `codestream`, and Huffman coded again. The frame header, the
+/// transform table, the bin center and the Huffman table identifiers of the blocks, so which blocks
+/// share a table, are kept, as are the comments with the bitrate of the NIST comment set to the bits
+/// per pixel of the coded data, which stays below `bitrate` if the bin widths of `codestream` are
+/// finer. Fails if even the coarsest allocation searched does not fit `bitrate`.
+pub fn requantize(codestream: &Codestream, bitrate: f64) -> io::Result<Codestream> {
+    let coefficients = dequantize_coefficients::<f64>(codestream)?;
+    let header = &codestream.frame_header;
+    let layout = SubbandLayout::new(header.width as usize, header.height as usize);
+    let variances = subband_variances(&coefficients, &layout);
+    let source = &codestream.quantization_table;
+    // the Huffman table identifiers of the source, unless it has another number of blocks
+    let source_ids = codestream.blocks.iter().map(|b| b.huffman_table_id).collect::<Vec<_>>();
+    let table_ids = if source_ids.len() == BLOCK_ENDS.len() && source_ids.iter().all(|id| (*id as usize) < MAX_HUFFMAN_TABLES) {
+        source_ids
+    } else {
+        TableSharing::of(&source_ids).table_ids(BLOCK_ENDS.len())
+    };
+    // the bin widths for `allocation` bits per pixel, or those of the source if they are coarser
+    let table_for = |allocation: f64| {
+        let target = QuantizationTable::for_bitrate(&variances, &layout, allocation);
+        let (mut bin_widths, mut zero_bin_widths) = (vec![0.; NUM_SUBBANDS], vec![0.; NUM_SUBBANDS]);
+        for k in (0..NUM_SUBBANDS).filter(|k| source.is_quantized(*k) && target.is_quantized(*k)) {
+            (bin_widths[k], zero_bin_widths[k]) = if transmitted(target.bin_widths[k]) > source.bin_widths[k] {
+                (transmitted(target.bin_widths[k]), transmitted(target.zero_bin_widths[k]))
+            } else {
+                (source.bin_widths[k], source.zero_bin_widths[k])
+            };
+        }
+        let table = QuantizationTable::new(source.bin_center, bin_widths, zero_bin_widths);
+        let (blocks, _) = code_subbands(&coefficients, &layout, &table, &variances, table_ids.clone())?;
+        Ok::<_, io::Error>((table, blocks))
+    };
+    let coded_bytes = |blocks: &[Block]| blocks.iter().flat_map(|b| &b.data).map(Vec::len).sum::<usize>() as f64;
+    let pixels = layout.width as f64 * layout.height as f64;
+    let budget = bitrate * pixels / 8.;
+
+    // coefficients quantized before lie further from zero than the original ones, so fewer fall in
+    // the zero bins than in a direct compression and the allocation is lowered until the coded
+    // data fits the bitrate
+    let (mut quantization_table, mut blocks) = table_for(bitrate)?;
+    if coded_bytes(&blocks) > budget {
+        let (mut low, mut high) = (0., bitrate);
+        let (mut fitting, mut coarsest) = (None, coded_bytes(&blocks));
+        for _ in 0..REQUANTIZE_STEPS {
+            let allocation = (low + high) / 2.;
+            let (table, candidate) = table_for(allocation)?;
+            if coded_bytes(&candidate) > budget {
+                high = allocation;
+                coarsest = coded_bytes(&candidate);
+            } else {
+                low = allocation;
+                fitting = Some((table, candidate));
+            }
+        }
+        (quantization_table, blocks) = fitting.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "Cannot requantize to {} bits per pixel, the coarsest allocation takes {:.6}", bitrate, coarsest * 8. / pixels)))?;
+    }
+    let achieved = coded_bytes(&blocks) * 8. / pixels;
+
+    let comments = codestream.comments.iter()
+        .map(|comment| if comment.starts_with(b"NIST_COM") {
+            String::from_utf8_lossy(comment).lines()
+                .map(|line| if line.starts_with("WSQ_BITRATE ") { format!("WSQ_BITRATE {:.6}", achieved) } else { line.to_string() })
+                .collect::<Vec<_>>().join("\n").into_bytes()
+        } else {
+            comment.clone()
+        })
+        .collect();
+    Ok(Codestream {
+        frame_header: header.clone(),
+        transform_table: codestream.transform_table.clone(),
+        quantization_table,
+        restart_interval: 0,
+        comments,
+        blocks,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io;
+
+    use super::{bitrate_from_comments, decode, decode_codestream, decode_coefficients, decode_image_data, decode_with, encode,
+                encode_codestream, encode_codestream_with_sharing, encode_with_statistics, encode_with_table, requantize,
+                DEFAULT_BITRATE};
+    use crate::entropy::TableSharing;
+    use crate::codestream::Codestream;
+    use crate::gray::GrayImage;
+    use crate::quantization::QuantizationTable;
+    use crate::swt::fixed::Fixed;
+    use crate::swt::layout::NUM_QUANTIZED_SUBBANDS;
+
+    /// Ridges of varying direction and spacing over a smooth background, with some noise.
+    pub(crate) fn fingerprint(width: usize, height: usize) -> GrayImage {
+        let mut seed = 12345u32;
+        let data = (0..width * height).map(|i| {
+            let (x, y) = ((i % width) as f64, (i / width) as f64);
+            let (dx, dy) = (x - width as f64 / 2., y - height as f64 / 2.);
+            let radius = (dx * dx + 1.5 * dy * dy).sqrt();
+            let ridges = (radius / (4. + x / width as f64 * 3.)).sin();
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let noise = ((seed >> 16) % 21) as f64 - 10.;
+            (128. + 80. * ridges + 20. * (y / height as f64) + noise).clamp(0., 255.) as u8
+        }).collect();
+        GrayImage::new(width, height, data).unwrap()
+    }
+
+    fn rmse(a: &GrayImage, b: &GrayImage) -> f64 {
+        let sum = a.data.iter().zip(&b.data).map(|(a, b)| (*a as f64 - *b as f64).powi(2)).sum::<f64>();
+        (sum / a.data.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let image = fingerprint(416, 432).with_ppi(500);
+        let compressed = encode(&image, DEFAULT_BITRATE).unwrap();
+        let bitrate = compressed.len() as f64 * 8. / (image.width * image.height) as f64;
+        assert!((0.5..1.).contains(&bitrate), "bitrate {}", bitrate);
+        let decoded = decode(&compressed).unwrap();
+        assert_eq!((416, 432, Some(500)), (decoded.width, decoded.height, decoded.ppi));
+        assert!(rmse(&image, &decoded) < 12., "rmse {}", rmse(&image, &decoded));
+
+        let fine = decode(&encode(&image, 2.25).unwrap()).unwrap();
+        assert!(rmse(&image, &fine) < rmse(&image, &decoded));
+    }
+
+    #[test]
+    fn test_codestream_round_trip() {
+        let compressed = encode(&fingerprint(400, 411), DEFAULT_BITRATE).unwrap();
+        let codestream = Codestream::parse(&compressed).unwrap();
+        assert_eq!(3, codestream.blocks.len());
+        assert_eq!(vec![0, 1, 1], codestream.blocks.iter().map(|b| b.huffman_table_id).collect::<Vec<_>>());
+        assert_eq!(compressed, codestream.encode());
+    }
+
+    #[test]
+    fn test_statistics() {
+        // blurred, so some of the finest subbands are discarded
+        let mut image = fingerprint(300, 320);
+        let original = image.data.clone();
+        for (i, pixel) in image.data.iter_mut().enumerate() {
+            let (x, y) = (i % 300, i / 300);
+            let sum = (0..25).map(|j| original[(y + j / 5).min(319) * 300 + (x + j % 5).min(299)] as u32).sum::<u32>();
+            *pixel = (sum / 25) as u8;
+        }
+        let (codestream, statistics) = encode_with_statistics(&image, DEFAULT_BITRATE).unwrap();
+        assert_eq!((0..60).collect::<Vec<_>>(), statistics.iter().map(|s| s.index).collect::<Vec<_>>());
+        assert!(statistics.iter().any(|s| s.is_discarded()));
+        assert!(statistics.iter().filter(|s| s.is_discarded()).all(|s| s.nonzero == 0 && s.bits == 0));
+        assert_eq!(codestream.quantization_table.bin_widths[..60],
+                   statistics.iter().map(|s| s.bin_width).collect::<Vec<_>>());
+        let coefficients = decode_coefficients(&codestream).unwrap();
+        assert_eq!(coefficients.iter().filter(|p| **p != 0).count(), statistics.iter().map(|s| s.nonzero).sum::<usize>());
+
+        // the coded bits of every block, less the stuffed bytes and at most 7 bits of padding
+        for (block, subbands) in codestream.blocks.iter().zip([0..19, 19..52, 52..60]) {
+            let data = &block.data[0];
+            let stuffed = data.windows(2).filter(|w| w == &[0xFF, 0]).count();
+            let bits = statistics[subbands].iter().map(|s| s.bits).sum::<usize>();
+            assert!((0..8).contains(&((data.len() - stuffed) * 8 - bits)));
+        }
+    }
+
+    #[test]
+    fn test_explicit_table() {
+        let image = fingerprint(320, 300);
+        let reference = Codestream::parse(&encode(&image, DEFAULT_BITRATE).unwrap()).unwrap();
+        let (codestream, _) = encode_with_table(&image, &reference.quantization_table).unwrap();
+        assert_eq!((&reference.quantization_table, &reference.blocks), (&codestream.quantization_table, &codestream.blocks));
+        assert!(!String::from_utf8_lossy(&codestream.comments[0]).contains("WSQ_BITRATE"));
+
+        // drop subband 30 and coarsen subband 10
+        let mut table = reference.quantization_table.clone();
+        table.bin_widths[30] = 0.;
+        table.bin_widths[10] *= 2.;
+        let (codestream, statistics) = encode_with_table(&image, &table).unwrap();
+        assert!(statistics[30].is_discarded() && !codestream.quantization_table.is_quantized(30));
+        assert_eq!(table.bin_widths[10], codestream.quantization_table.bin_widths[10]);
+        assert!(decode(&codestream.encode()).is_ok());
+
+        table.zero_bin_widths[5] = -1.;
+        assert!(encode_with_table(&image, &table).is_err());
+    }
+
+    #[test]
+    fn test_untransmittable_table() {
+        let image = fingerprint(64, 64);
+        let table = |bin_width: f64| QuantizationTable::new(0.44, vec![bin_width; NUM_QUANTIZED_SUBBANDS], vec![bin_width; NUM_QUANTIZED_SUBBANDS]);
+        // no digit of the bin width is left at the largest scale
+        let error = encode_with_table(&image, &table(1e-300)).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        // bin indices beyond 16 bits are not clamped into wrong pixels
+        let error = encode_with_table(&image, &table(1e-4)).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        assert!(error.to_string().contains("does not fit in 16 bits"));
+        assert!(encode_with_table(&image, &table(0.1)).is_ok());
+    }
+
+    #[test]
+    fn test_requantize() {
+        let image = fingerprint(320, 336);
+        let source = Codestream::parse(&encode(&image, 2.).unwrap()).unwrap();
+        let requantized = requantize(&source, DEFAULT_BITRATE).unwrap();
+        let (size, direct_size) = (requantized.encode().len(), encode(&image, DEFAULT_BITRATE).unwrap().len());
+        assert!(size < source.encode().len() / 2 && size.abs_diff(direct_size) < direct_size / 8, "{} {}", size, direct_size);
+        assert_eq!((&source.frame_header, &source.transform_table), (&requantized.frame_header, &requantized.transform_table));
+        // the comment records the bits per pixel of the coded data, within the requested bitrate
+        let coded_bitrate = |codestream: &Codestream| {
+            codestream.blocks.iter().flat_map(|b| &b.data).map(Vec::len).sum::<usize>() as f64 * 8. / (320. * 336.)
+        };
+        let achieved = bitrate_from_comments(&requantized.comments).unwrap();
+        assert!((achieved - coded_bitrate(&requantized)).abs() < 1e-6 && (0.6..=DEFAULT_BITRATE).contains(&achieved), "{}", achieved);
+        let decoded = decode_codestream::<f64>(&requantized).unwrap();
+        let direct = decode(&encode(&image, DEFAULT_BITRATE).unwrap()).unwrap();
+        assert!(rmse(&image, &decoded) < rmse(&image, &direct) * 1.2, "{} {}", rmse(&image, &decoded), rmse(&image, &direct));
+
+        // even the coarsest allocation searched takes more than the bitrate
+        let error = requantize(&source, 0.0001).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        assert!(error.to_string().starts_with("Cannot requantize to 0.0001 bits per pixel"), "{}", error);
+
+        // bin widths never get finer, so a higher bitrate keeps the coefficients
+        let same = requantize(&source, 4.).unwrap();
+        assert_eq!(source.quantization_table, same.quantization_table);
+        assert_eq!(decode_coefficients(&source).unwrap(), decode_coefficients(&same).unwrap());
+        let achieved = bitrate_from_comments(&same.comments).unwrap();
+        assert!((achieved - coded_bitrate(&same)).abs() < 1e-6 && achieved < 2., "{}", achieved);
+
+        // all blocks under one identifier, with the table redefined before each
+        let mut single = source.clone();
+        for block in &mut single.blocks {
+            block.huffman_table_id = 0;
+        }
+        let requantized = requantize(&Codestream::parse(&single.encode()).unwrap(), DEFAULT_BITRATE).unwrap();
+        assert_eq!(vec![0, 0, 0], requantized.blocks.iter().map(|b| b.huffman_table_id).collect::<Vec<_>>());
+        assert!(requantized.blocks.iter().all(|b| b.huffman_table == requantized.blocks[0].huffman_table));
+        assert!(decode(&requantized.encode()).is_ok());
+    }
+
+    #[test]
+    fn test_table_sharing() {
+        let image = fingerprint(300, 310);
+        let shared = encode_codestream(&image, DEFAULT_BITRATE).unwrap();
+        let separate = encode_codestream_with_sharing(&image, DEFAULT_BITRATE, TableSharing::Separate).unwrap();
+        assert_eq!(vec![0, 1, 2], separate.blocks.iter().map(|b| b.huffman_table_id).collect::<Vec<_>>());
+        let separate = Codestream::parse(&separate.encode()).unwrap();
+        let image_data = decode_image_data(&separate).unwrap();
+        assert_eq!(TableSharing::Separate, image_data.sharing());
+        assert_eq!(decode_image_data(&shared).unwrap().blocks, image_data.blocks);
+        assert_eq!(decode_codestream::<f64>(&shared).unwrap(), decode_codestream::<f64>(&separate).unwrap());
+        assert_eq!(TableSharing::Separate, decode_image_data(&requantize(&separate, 0.5).unwrap()).unwrap().sharing());
+    }
+
+    #[test]
+    fn test_sample_types() {
+        let compressed = encode(&fingerprint(410, 400), DEFAULT_BITRATE).unwrap();
+        let reference = decode(&compressed).unwrap();
+        for decoded in [decode_with::<f32>(&compressed).unwrap(), decode_with::<Fixed>(&compressed).unwrap()] {
+            assert!(reference.data.iter().zip(&decoded.data).all(|(a, b)| a.abs_diff(*b) <= 1));
+        }
+    }
+
+    #[test]
+    fn test_flat_image() {
+        let image = GrayImage::new(64, 64, vec![90; 64 * 64]).unwrap();
+        assert_eq!(image, decode(&encode(&image, DEFAULT_BITRATE).unwrap()).unwrap());
+    }
+}
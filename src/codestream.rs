@@ -0,0 +1,608 @@
+//! Module for the marker segments of WSQ compressed data, Annex B
+//
+// Every marker is X'FF' followed by a byte that is neither 0 nor X'FF'. Apart from SOI, EOI and the
+// restart markers each marker starts a segment whose 16-bit length counts itself and the
+// parameters that follow. Real numbers are written as an integer with a decimal scale exponent,
+// the integer is shifted left as many decimal places as fit in its 16 or 32 bits.
+use std::io;
+
+use crate::entropy::{markers, HuffmanTable, MAX_HUFFMAN_TABLES};
+use crate::quantization::QuantizationTable;
+use crate::swt::filter::Filter;
+use crate::swt::layout::NUM_SUBBANDS;
+use crate::swt::Sample;
+
+pub mod dump;
+
+/// Encoder number two, the only FBI approved parameterization
+pub const ENCODER_NUMBER_TWO: u8 = 2;
+/// The most parameter bytes the 16-bit length of a segment allows for
+pub const MAX_SEGMENT_PAYLOAD: usize = u16::MAX as usize - 2;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// `value` as a scale exponent and an integer of at most `max`, keeping as many decimals as fit.
+/// Values too small for a scale of 255 keep fewer digits, down to none.
+fn to_scaled(value: f64, max: u32) -> (u8, u32) {
+    let max_f = max as f64;
+    if value == 0. {
+        return (0, 0);
+    }
+    if value >= max_f {
+        return (0, value.round().min(max_f) as u32);
+    }
+    let (mut scale, mut scaled) = (0u8, value);
+    while scaled < max_f {
+        if scale == u8::MAX {
+            return (scale, scaled.round() as u32);
+        }
+        scale += 1;
+        scaled *= 10.;
+    }
+    (scale - 1, (scaled / 10.).round() as u32)
+}
+
+fn from_scaled(scale: u8, value: u32) -> f64 {
+    value as f64 / 10f64.powi(scale as i32)
+}
+
+/// The value a decoder reads back for a parameter written as a scale and a 16-bit integer.
+pub fn transmitted(value: f64) -> f64 {
+    let (scale, value) = to_scaled(value, u16::MAX as u32);
+    from_scaled(scale, value)
+}
+
+/// Whether `value` can be written as a scale and a 16-bit integer: 0, or up to 65535 and not so
+/// small that no digit of it is left at a scale of 255.
+pub fn is_transmittable(value: f64) -> bool {
+    (0. ..=u16::MAX as f64).contains(&value) && (value == 0. || to_scaled(value, u16::MAX as u32).1 > 0)
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_scaled_u16(out: &mut Vec<u8>, value: f64) {
+    let (scale, value) = to_scaled(value, u16::MAX as u32);
+    out.push(scale);
+    put_u16(out, value as u16);
+}
+
+/// Reads the parameters of a marker segment.
+struct Parameters<'a> {
+    payload: &'a [u8],
+    position: usize,
+    name: &'static str,
+}
+
+impl<'a> Parameters<'a> {
+    fn new(payload: &'a [u8], name: &'static str) -> Self {
+        Self { payload, position: 0, name }
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.payload.get(self.position..self.position + len)
+            .ok_or_else(|| invalid_data(format!("{} segment too short", self.name)))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn scaled_u16(&mut self) -> io::Result<f64> {
+        let scale = self.u8()?;
+        Ok(from_scaled(scale, self.u16()? as u32))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.payload.len()
+    }
+}
+
+/// Source image characteristics and encoder version, Figure B.3.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameHeader {
+    /// Scanner black calibration value
+    pub black: u8,
+    /// Scanner white calibration value
+    pub white: u8,
+    pub height: u16,
+    pub width: u16,
+    /// Shift M of the normalization
+    pub mean: f64,
+    /// Scale R of the normalization
+    pub rescale: f64,
+    pub encoder: u8,
+    /// Identifies the software implementation that encoded the image
+    pub software: u16,
+}
+
+impl FrameHeader {
+    fn parse(payload: &[u8]) -> io::Result<Self> {
+        let mut parameters = Parameters::new(payload, "Frame header");
+        Ok(Self {
+            black: parameters.u8()?,
+            white: parameters.u8()?,
+            height: parameters.u16()?,
+            width: parameters.u16()?,
+            mean: parameters.scaled_u16()?,
+            rescale: parameters.scaled_u16()?,
+            encoder: parameters.u8()?,
+            software: parameters.u16()?,
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend([self.black, self.white]);
+        put_u16(out, self.height);
+        put_u16(out, self.width);
+        put_scaled_u16(out, self.mean);
+        put_scaled_u16(out, self.rescale);
+        out.push(self.encoder);
+        put_u16(out, self.software);
+    }
+}
+
+/// Analysis filters of the transform, Figure B.6. Both filters are whole-sample symmetric and only
+/// the coefficients from the center on are given, as in `Filter::WSS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformTable {
+    pub lowpass: Vec<f64>,
+    pub highpass: Vec<f64>,
+}
+
+impl Default for TransformTable {
+    /// The filters of encoder number two.
+    fn default() -> Self {
+        Self {
+            lowpass: vec![0.85269867900940, 0.37740285561265, -0.11062440441842, -0.02384946501938, 0.037828455506995],
+            highpass: vec![0.78848561640566, -0.41809227322221, -0.040689417609558, 0.064538882628938],
+        }
+    }
+}
+
+impl TransformTable {
+    pub fn filters<F: Sample>(&self) -> (Filter<F>, Filter<F>) {
+        let convert = |coefficients: &[f64]| coefficients.iter().map(|c| F::from_f64(*c)).collect();
+        (Filter::WSS(convert(&self.lowpass)), Filter::WSS(convert(&self.highpass)))
+    }
+
+    fn parse(payload: &[u8]) -> io::Result<Self> {
+        let mut parameters = Parameters::new(payload, "Transform table");
+        let lowpass_len = parameters.u8()?;
+        let highpass_len = parameters.u8()?;
+        if lowpass_len % 2 == 0 || highpass_len % 2 == 0 {
+            return Err(invalid_data(format!("Filters of length {} and {} are not whole-sample symmetric",
+                                            lowpass_len, highpass_len)));
+        }
+        let mut coefficients = |len: u8| (0..len / 2 + 1).map(|_| {
+            let negative = parameters.u8()? != 0;
+            let scale = parameters.u8()?;
+            let value = from_scaled(scale, parameters.u32()?);
+            Ok(if negative { -value } else { value })
+        }).collect::<io::Result<Vec<_>>>();
+        let lowpass = coefficients(lowpass_len)?;
+        let highpass = coefficients(highpass_len)?;
+        Ok(Self { lowpass, highpass })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push((self.lowpass.len() * 2 - 1) as u8);
+        out.push((self.highpass.len() * 2 - 1) as u8);
+        for coefficient in self.lowpass.iter().chain(self.highpass.iter()) {
+            let (scale, value) = to_scaled(coefficient.abs(), u32::MAX);
+            out.extend([(*coefficient < 0.) as u8, scale]);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn parse_quantization_table(payload: &[u8]) -> io::Result<QuantizationTable> {
+    let mut parameters = Parameters::new(payload, "Quantization table");
+    let bin_center = parameters.scaled_u16()?;
+    let (mut bin_widths, mut zero_bin_widths) = (vec![], vec![]);
+    for _ in 0..NUM_SUBBANDS {
+        bin_widths.push(parameters.scaled_u16()?);
+        zero_bin_widths.push(parameters.scaled_u16()?);
+    }
+    Ok(QuantizationTable::new(bin_center, bin_widths, zero_bin_widths))
+}
+
+fn write_quantization_table(table: &QuantizationTable, out: &mut Vec<u8>) {
+    put_scaled_u16(out, table.bin_center);
+    for k in 0..NUM_SUBBANDS {
+        put_scaled_u16(out, table.bin_widths.get(k).copied().unwrap_or(0.));
+        put_scaled_u16(out, table.zero_bin_widths.get(k).copied().unwrap_or(0.));
+    }
+}
+
+fn parse_huffman_tables(payload: &[u8]) -> io::Result<Vec<(u8, HuffmanTable)>> {
+    let mut parameters = Parameters::new(payload, "Huffman table");
+    let mut tables = vec![];
+    while !parameters.is_empty() {
+        let id = parameters.u8()?;
+        if id as usize >= MAX_HUFFMAN_TABLES {
+            return Err(invalid_data(format!("Huffman table identifier {} out of range", id)));
+        }
+        let mut bits = [0; 16];
+        bits.copy_from_slice(parameters.bytes(16)?);
+        let count = bits.iter().map(|b| *b as usize).sum();
+        tables.push((id, HuffmanTable::new(bits, parameters.bytes(count)?.to_vec())?));
+    }
+    Ok(tables)
+}
+
+/// A marker with its parameters, or the data between markers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    StartOfImage,
+    EndOfImage,
+    FrameHeader(FrameHeader),
+    /// Block header, selecting the Huffman table of the block
+    BlockHeader(u8),
+    TransformTable(TransformTable),
+    QuantizationTable(QuantizationTable),
+    HuffmanTables(Vec<(u8, HuffmanTable)>),
+    /// Number of coefficients between restart markers, 0 disables them
+    RestartInterval(u16),
+    Comment(Vec<u8>),
+    /// Restart marker with its modulo 8 count
+    Restart(u8),
+    /// Entropy-coded data segment, with the stuffed zero bytes
+    EntropyCodedData(Vec<u8>),
+    /// A marker this module does not know, with the parameters of its segment
+    Unknown(u16, Vec<u8>),
+}
+
+impl Segment {
+    pub fn marker(&self) -> Option<u16> {
+        Some(match self {
+            Segment::StartOfImage => markers::SOI,
+            Segment::EndOfImage => markers::EOI,
+            Segment::FrameHeader(_) => markers::SOF,
+            Segment::BlockHeader(_) => markers::SOB,
+            Segment::TransformTable(_) => markers::DTT,
+            Segment::QuantizationTable(_) => markers::DQT,
+            Segment::HuffmanTables(_) => markers::DHT,
+            Segment::RestartInterval(_) => markers::DRI,
+            Segment::Comment(_) => markers::COM,
+            Segment::Restart(m) => markers::RST_M + *m as u16,
+            Segment::EntropyCodedData(_) => return None,
+            Segment::Unknown(marker, _) => *marker,
+        })
+    }
+
+    fn parse(marker: u16, payload: &[u8]) -> io::Result<Self> {
+        Ok(match marker {
+            markers::SOF => Segment::FrameHeader(FrameHeader::parse(payload)?),
+            markers::SOB => Segment::BlockHeader(Parameters::new(payload, "Block header").u8()?),
+            markers::DTT => Segment::TransformTable(TransformTable::parse(payload)?),
+            markers::DQT => Segment::QuantizationTable(parse_quantization_table(payload)?),
+            markers::DHT => Segment::HuffmanTables(parse_huffman_tables(payload)?),
+            markers::DRI => Segment::RestartInterval(Parameters::new(payload, "Restart interval").u16()?),
+            markers::COM => Segment::Comment(payload.to_vec()),
+            _ => Segment::Unknown(marker, payload.to_vec()),
+        })
+    }
+
+    /// Append the segment to `out`, its parameters must not take more than `MAX_SEGMENT_PAYLOAD`
+    /// bytes.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let Some(marker) = self.marker() else {
+            if let Segment::EntropyCodedData(data) = self {
+                out.extend_from_slice(data);
+            }
+            return;
+        };
+        put_u16(out, marker);
+        let mut payload = vec![];
+        match self {
+            Segment::StartOfImage | Segment::EndOfImage | Segment::Restart(_) | Segment::EntropyCodedData(_) => return,
+            Segment::FrameHeader(header) => header.write(&mut payload),
+            Segment::BlockHeader(table_id) => payload.push(*table_id),
+            Segment::TransformTable(table) => table.write(&mut payload),
+            Segment::QuantizationTable(table) => write_quantization_table(table, &mut payload),
+            Segment::HuffmanTables(tables) => for (id, table) in tables {
+                payload.push(*id);
+                payload.extend_from_slice(&table.bits);
+                payload.extend_from_slice(&table.values);
+            },
+            Segment::RestartInterval(interval) => put_u16(&mut payload, *interval),
+            Segment::Comment(text) | Segment::Unknown(_, text) => payload.extend_from_slice(text),
+        }
+        put_u16(out, payload.len() as u16 + 2);
+        out.extend(payload);
+    }
+}
+
+/// Iterates over the segments of compressed data with their byte offsets, up to and including EOI.
+pub struct Parser<'a> {
+    contents: &'a [u8],
+    position: usize,
+    done: bool,
+    /// Start of the last segment, when its parameters failed to parse but its length was valid
+    skippable: Option<usize>,
+    raw: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(contents: &'a [u8]) -> Self {
+        Self { contents, position: 0, done: false, skippable: None, raw: false }
+    }
+
+    /// Continue after a segment whose parameters failed to parse, with that segment and the ones
+    /// after it returned as `Segment::Unknown` with their parameters uninterpreted. False when the
+    /// last error left no segment length to skip by.
+    pub fn resume_raw(&mut self) -> bool {
+        let Some(start) = self.skippable.take() else {
+            return false;
+        };
+        self.position = start;
+        self.raw = true;
+        self.done = false;
+        true
+    }
+
+    fn is_marker_at(&self, position: usize) -> bool {
+        self.contents.get(position) == Some(&0xFF) && self.contents.get(position + 1).is_some_and(|b| *b != 0)
+    }
+
+    fn next_segment(&mut self) -> io::Result<(usize, Segment)> {
+        let start = self.position;
+        self.skippable = None;
+        if !self.is_marker_at(start) {
+            if start >= self.contents.len() {
+                return Err(invalid_data("Compressed data ends without EOI".to_string()));
+            }
+            // entropy-coded data runs to the next marker
+            let mut end = start;
+            while end < self.contents.len() && !self.is_marker_at(end) {
+                end += if self.contents[end] == 0xFF { 2 } else { 1 };
+            }
+            self.position = end.min(self.contents.len());
+            return Ok((start, Segment::EntropyCodedData(self.contents[start..self.position].to_vec())));
+        }
+        // fill bytes may precede a marker
+        let mut position = start;
+        while self.contents.get(position + 1) == Some(&0xFF) {
+            position += 1;
+        }
+        let marker = u16::from_be_bytes([0xFF, self.contents.get(position + 1).copied().unwrap_or(0)]);
+        self.position = position + 2;
+        let segment = match marker {
+            markers::SOI => Segment::StartOfImage,
+            markers::EOI => Segment::EndOfImage,
+            m if (markers::RST_M..markers::RST_M + 8).contains(&m) => Segment::Restart((m - markers::RST_M) as u8),
+            m => {
+                let length = self.contents.get(self.position..self.position + 2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                    .filter(|length| *length >= 2)
+                    .ok_or_else(|| invalid_data(format!("Invalid segment length for marker {:04X} at byte {}", m, position)))?;
+                let payload = self.contents.get(self.position + 2..self.position + length)
+                    .ok_or_else(|| invalid_data(format!("Segment of marker {:04X} at byte {} runs past the end", m, position)))?;
+                self.position += length;
+                if self.raw {
+                    Segment::Unknown(m, payload.to_vec())
+                } else {
+                    match Segment::parse(m, payload) {
+                        Ok(segment) => segment,
+                        Err(e) => {
+                            self.skippable = Some(start);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        };
+        Ok((position, segment))
+    }
+}
+
+impl Iterator for Parser<'_> {
+    type Item = io::Result<(usize, Segment)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let segment = self.next_segment();
+        self.done = !matches!(segment, Ok((_, ref s)) if *s != Segment::EndOfImage);
+        Some(segment)
+    }
+}
+
+/// The entropy-coded data of one block, split at its restart markers, with the Huffman table that
+/// was installed under the block's table identifier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub huffman_table_id: u8,
+    pub huffman_table: HuffmanTable,
+    pub data: Vec<Vec<u8>>,
+}
+
+/// A compressed image in the interchange format, with every table needed to decode it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Codestream {
+    pub frame_header: FrameHeader,
+    pub transform_table: TransformTable,
+    pub quantization_table: QuantizationTable,
+    pub restart_interval: u16,
+    pub comments: Vec<Vec<u8>>,
+    pub blocks: Vec<Block>,
+}
+
+impl Codestream {
+    pub fn parse(contents: &[u8]) -> io::Result<Self> {
+        let mut parser = Parser::new(contents);
+        match parser.next().transpose()? {
+            Some((_, Segment::StartOfImage)) => {}
+            _ => return Err(invalid_data("Not WSQ compressed data, expected SOI".to_string())),
+        }
+        let (mut frame_header, mut transform_table, mut quantization_table) = (None, None, None);
+        let mut huffman_tables: [Option<HuffmanTable>; MAX_HUFFMAN_TABLES] = Default::default();
+        let mut restart_interval = 0;
+        let mut comments = vec![];
+        let mut blocks: Vec<Block> = vec![];
+        for segment in parser {
+            let (offset, segment) = segment?;
+            match segment {
+                Segment::FrameHeader(header) => frame_header = Some(header),
+                Segment::TransformTable(table) => transform_table = Some(table),
+                Segment::QuantizationTable(table) => quantization_table = Some(table),
+                Segment::HuffmanTables(tables) => for (id, table) in tables {
+                    huffman_tables[id as usize] = Some(table);
+                },
+                Segment::RestartInterval(interval) => restart_interval = interval,
+                Segment::Comment(text) => comments.push(text),
+                Segment::BlockHeader(id) => {
+                    let huffman_table = huffman_tables.get(id as usize).cloned().flatten()
+                        .ok_or_else(|| invalid_data(format!("Block at byte {} uses undefined Huffman table {}", offset, id)))?;
+                    blocks.push(Block { huffman_table_id: id, huffman_table, data: vec![vec![]] });
+                }
+                Segment::Restart(_) => match blocks.last_mut() {
+                    Some(block) => block.data.push(vec![]),
+                    None => return Err(invalid_data(format!("Restart marker outside a block at byte {}", offset))),
+                },
+                Segment::EntropyCodedData(data) => match blocks.last_mut().and_then(|b| b.data.last_mut()) {
+                    Some(segment) => segment.extend(data),
+                    None => return Err(invalid_data(format!("Entropy-coded data outside a block at byte {}", offset))),
+                },
+                Segment::Unknown(marker, _) => log::warn!("Skipping unknown marker {:04X} at byte {}", marker, offset),
+                Segment::StartOfImage => return Err(invalid_data(format!("Unexpected SOI at byte {}", offset))),
+                Segment::EndOfImage => break,
+            }
+        }
+        let missing = |name: &str| invalid_data(format!("Compressed data has no {}", name));
+        Ok(Self {
+            frame_header: frame_header.ok_or_else(|| missing("frame header"))?,
+            transform_table: transform_table.ok_or_else(|| missing("transform table"))?,
+            quantization_table: quantization_table.ok_or_else(|| missing("quantization table"))?,
+            restart_interval,
+            comments,
+            blocks,
+        })
+    }
+
+    /// The interchange format, a Huffman table is written before the first block using it.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        Segment::StartOfImage.write(&mut out);
+        for comment in &self.comments {
+            // a comment longer than a segment holds continues in the next ones
+            let parts = if comment.is_empty() { vec![&comment[..]] } else { comment.chunks(MAX_SEGMENT_PAYLOAD).collect() };
+            for part in parts {
+                Segment::Comment(part.to_vec()).write(&mut out);
+            }
+        }
+        Segment::TransformTable(self.transform_table.clone()).write(&mut out);
+        Segment::QuantizationTable(self.quantization_table.clone()).write(&mut out);
+        if self.restart_interval != 0 {
+            Segment::RestartInterval(self.restart_interval).write(&mut out);
+        }
+        Segment::FrameHeader(self.frame_header.clone()).write(&mut out);
+        let mut installed: [Option<&HuffmanTable>; MAX_HUFFMAN_TABLES] = Default::default();
+        for block in &self.blocks {
+            if installed[block.huffman_table_id as usize] != Some(&block.huffman_table) {
+                Segment::HuffmanTables(vec![(block.huffman_table_id, block.huffman_table.clone())]).write(&mut out);
+                installed[block.huffman_table_id as usize] = Some(&block.huffman_table);
+            }
+            Segment::BlockHeader(block.huffman_table_id).write(&mut out);
+            for (i, data) in block.data.iter().enumerate() {
+                if i > 0 {
+                    Segment::Restart((i - 1) as u8 % 8).write(&mut out);
+                }
+                out.extend_from_slice(data);
+            }
+        }
+        Segment::EndOfImage.write(&mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_scaled, is_transmittable, to_scaled, transmitted, Codestream, FrameHeader, Parser, Segment, TransformTable,
+                MAX_SEGMENT_PAYLOAD};
+    use crate::codec::tests::fingerprint;
+    use crate::codec::{encode_codestream, DEFAULT_BITRATE};
+    use crate::entropy::HuffmanTable;
+
+    #[test]
+    fn test_scaled() {
+        assert_eq!((2, 12743), to_scaled(127.43, 65535));
+        assert_eq!((0, 0), to_scaled(0., 65535));
+        assert_eq!((0, 65535), to_scaled(70000., 65535));
+        assert_eq!(0.44, from_scaled(2, 44));
+        assert!((transmitted(0.123456789) - 0.123456789).abs() < 1e-5);
+        let (scale, value) = to_scaled(0.85269867900940, u32::MAX);
+        assert_eq!((9, 852698679), (scale, value));
+
+        assert_eq!((255, 0), to_scaled(1e-300, 65535));
+        assert_eq!((255, 100), to_scaled(1e-253, 65535));
+        assert!(is_transmittable(0.) && is_transmittable(1e-4) && is_transmittable(65535.));
+        assert!(!is_transmittable(1e-300) && !is_transmittable(-1.) && !is_transmittable(f64::NAN));
+    }
+
+    #[test]
+    fn test_segments_round_trip() {
+        let segments = vec![
+            Segment::StartOfImage,
+            Segment::Comment(b"a comment".to_vec()),
+            Segment::TransformTable(TransformTable::default()),
+            Segment::FrameHeader(FrameHeader { black: 0, white: 255, height: 480, width: 640, mean: 127.43, rescale: 1.05, encoder: 2, software: 0 }),
+            Segment::HuffmanTables(vec![(0, HuffmanTable::new([0, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], vec![1, 179, 181]).unwrap())]),
+            Segment::BlockHeader(0),
+            Segment::EntropyCodedData(vec![0x12, 0xFF, 0x00, 0x34]),
+            Segment::Restart(0),
+            Segment::EntropyCodedData(vec![0x56]),
+            Segment::EndOfImage,
+        ];
+        let mut contents = vec![];
+        for segment in &segments {
+            segment.write(&mut contents);
+        }
+        let parsed = Parser::new(&contents).map(|s| s.unwrap().1).collect::<Vec<_>>();
+        assert_eq!(segments.len(), parsed.len());
+        for (expected, parsed) in segments.iter().zip(&parsed) {
+            match (expected, parsed) {
+                (Segment::TransformTable(a), Segment::TransformTable(b)) => {
+                    assert!(a.lowpass.iter().chain(&a.highpass).zip(b.lowpass.iter().chain(&b.highpass))
+                        .all(|(a, b)| (a - b).abs() < 1e-9));
+                }
+                _ => assert_eq!(expected, parsed),
+            }
+        }
+    }
+
+    #[test]
+    fn test_segment_lengths() {
+        let mut contents = vec![];
+        Segment::TransformTable(TransformTable::default()).write(&mut contents);
+        assert_eq!(58, u16::from_be_bytes([contents[2], contents[3]]));
+        assert_eq!([9, 7], contents[4..6]);
+    }
+
+    #[test]
+    fn test_long_comment() {
+        let mut codestream = encode_codestream(&fingerprint(64, 64), DEFAULT_BITRATE).unwrap();
+        let long = (0..MAX_SEGMENT_PAYLOAD + 10).map(|i| b'a' + (i % 26) as u8).collect::<Vec<_>>();
+        codestream.comments = vec![long.clone(), vec![]];
+        let parsed = Codestream::parse(&codestream.encode()).unwrap();
+        assert_eq!(vec![long[..MAX_SEGMENT_PAYLOAD].to_vec(), long[MAX_SEGMENT_PAYLOAD..].to_vec(), vec![]], parsed.comments);
+        assert_eq!(codestream.blocks, parsed.blocks);
+    }
+}
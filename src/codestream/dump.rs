@@ -0,0 +1,166 @@
+//! A listing of the segments of compressed data, for inspecting files that fail to decode
+//
+// Every segment the parser finds is listed with its byte offset, the bytes it spans up to the next
+// segment and its parameters, entropy-coded data with the block and restart interval it belongs
+// to. A summary of the entropy-coded bytes of every block and restart interval follows. Nothing is
+// checked beyond what the parser needs to find the segments, so tables may be undefined or
+// missing. A segment whose parameters fail to parse is listed with the error and skipped by its
+// length, the segments after it are listed with their parameters uninterpreted.
+use std::io;
+use std::io::Write;
+
+use crate::codestream::{Parser, Segment};
+use crate::entropy::markers;
+
+fn describe(segment: &Segment) -> String {
+    match segment {
+        Segment::StartOfImage | Segment::EndOfImage => String::new(),
+        Segment::FrameHeader(header) => format!(
+            "black {}, white {}, {}x{}, mean {}, rescale {}, encoder {}, software {}", header.black, header.white,
+            header.width, header.height, header.mean, header.rescale, header.encoder, header.software),
+        Segment::BlockHeader(id) => format!("Huffman table {}", id),
+        Segment::TransformTable(table) => format!("lowpass {} taps, highpass {} taps",
+                                                  table.lowpass.len() * 2 - 1, table.highpass.len() * 2 - 1),
+        Segment::QuantizationTable(table) => format!(
+            "bin center {}, {} quantized subbands", table.bin_center, (0..table.len()).filter(|k| table.is_quantized(*k)).count()),
+        Segment::HuffmanTables(tables) => tables.iter()
+            .map(|(id, table)| format!("table {} with {} codes", id, table.values.len()))
+            .collect::<Vec<_>>().join(", "),
+        Segment::RestartInterval(interval) => format!("interval {}", interval),
+        Segment::Comment(text) => {
+            let text = String::from_utf8_lossy(text);
+            let first_line = text.lines().next().unwrap_or_default();
+            format!("{:?}{}", first_line, if first_line.len() < text.len() { " ..." } else { "" })
+        },
+        Segment::Restart(m) => format!("count {}", m),
+        Segment::EntropyCodedData(_) => String::new(),
+        Segment::Unknown(marker, parameters) if markers::name(*marker).is_some() => format!("{} parameter bytes", parameters.len()),
+        Segment::Unknown(_, parameters) => format!("unknown marker, {} parameter bytes", parameters.len()),
+    }
+}
+
+/// Bytes of `segment` when it is the last one found, from its marker on.
+fn last_len(segment: &Segment) -> usize {
+    let mut out = vec![];
+    segment.write(&mut out);
+    out.len()
+}
+
+/// Write the listing of `contents` to `out`, returning the first parse error, if any.
+pub fn dump(contents: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let mut segments = vec![];
+    // the index of the segment every skipped error belongs to
+    let mut skipped = vec![];
+    let mut error = None;
+    let mut parser = Parser::new(contents);
+    while let Some(segment) = parser.next() {
+        match segment {
+            Ok(segment) => segments.push(segment),
+            Err(e) if parser.resume_raw() => skipped.push((segments.len(), e)),
+            Err(e) => error = Some(e),
+        }
+    }
+
+    writeln!(out, "  offset  marker      bytes  parameters")?;
+    // entropy-coded bytes of every restart interval of every block
+    let mut blocks: Vec<Vec<usize>> = vec![];
+    for (i, (offset, segment)) in segments.iter().enumerate() {
+        let len = segments.get(i + 1).map_or_else(|| last_len(segment), |(next, _)| next - offset);
+        let (marker, parameters) = match segment {
+            Segment::EntropyCodedData(_) => {
+                let (block, interval) = (blocks.len(), blocks.last().map_or(0, Vec::len));
+                if let Some(bytes) = blocks.last_mut().and_then(|intervals| intervals.last_mut()) {
+                    *bytes += len;
+                }
+                ("ECS".to_string(), format!("block {}, restart interval {}", block, interval))
+            },
+            _ => {
+                let marker = segment.marker().unwrap_or_default();
+                match segment {
+                    Segment::BlockHeader(_) | Segment::Unknown(markers::SOB, _) => blocks.push(vec![0]),
+                    Segment::Restart(_) => if let Some(intervals) = blocks.last_mut() {
+                        intervals.push(0);
+                    },
+                    _ => {},
+                }
+                let name = markers::name(marker).unwrap_or_else(|| "?".to_string());
+                let parameters = match skipped.iter().find(|(index, _)| *index == i) {
+                    Some((_, e)) => format!("error: {}", e),
+                    None => describe(segment),
+                };
+                (format!("{:04X} {}", marker, name), parameters)
+            },
+        };
+        writeln!(out, "{:>8}  {:<10} {:>6}  {}", offset, marker, len, parameters)?;
+    }
+    if let Some((offset, segment)) = segments.last() {
+        let end = offset + last_len(segment);
+        if *segment == Segment::EndOfImage && end < contents.len() {
+            writeln!(out, "{:>8}  {} bytes after EOI", end, contents.len() - end)?;
+        }
+    }
+
+    writeln!(out)?;
+    for (i, intervals) in blocks.iter().enumerate() {
+        let sizes = intervals.iter().map(|bytes| bytes.to_string()).collect::<Vec<_>>().join(" ");
+        writeln!(out, "block {}: {} entropy-coded bytes, per restart interval {}", i + 1, intervals.iter().sum::<usize>(), sizes)?;
+    }
+    match skipped.into_iter().next() {
+        Some((_, e)) => Err(e),
+        None => error.map_or(Ok(()), Err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dump;
+    use crate::codec::tests::fingerprint;
+    use crate::codec::{encode_codestream, DEFAULT_BITRATE};
+
+    #[test]
+    fn test_dump() {
+        let mut codestream = encode_codestream(&fingerprint(200, 210), DEFAULT_BITRATE).unwrap();
+        let data = codestream.blocks[2].data.remove(0);
+        // split the last block in two restart intervals, not inside a stuffed byte
+        let split = (data.len() / 2..).find(|i| data[i - 1] != 0xFF).unwrap();
+        codestream.blocks[2].data = vec![data[..split].to_vec(), data[split..].to_vec()];
+        codestream.restart_interval = 100;
+        let contents = codestream.encode();
+
+        let mut out = vec![];
+        dump(&contents, &mut out).unwrap();
+        let listing = String::from_utf8(out).unwrap();
+        let lines = listing.lines().collect::<Vec<_>>();
+        assert_eq!("       0  FFA0 SOI        2  ", lines[1]);
+        assert!(lines[2].starts_with("       2  FFA8 COM      "));
+        assert!(lines[2].ends_with("\"NIST_COM 9\" ..."));
+        assert!(listing.contains("FFA7 DRI        6  interval 100\n"));
+        assert!(listing.contains("FFA4 DTT       60  lowpass 9 taps, highpass 7 taps\n"));
+        assert!(listing.contains("FFB0 RST0       2  count 0\n"));
+        assert!(listing.contains("ECS    "));
+        assert!(listing.contains(&format!("{:>8}  FFA1 EOI        2  \n", contents.len() - 2)));
+        assert!(listing.contains(&format!("block 3: {} entropy-coded bytes, per restart interval {} {}\n",
+                                          data.len(), split, data.len() - split)));
+
+        let mut out = vec![];
+        assert!(dump(&contents[..contents.len() / 2], &mut out).is_err());
+        assert!(String::from_utf8(out).unwrap().contains("FFA3 SOB"));
+    }
+
+    #[test]
+    fn test_dump_past_errors() {
+        let mut contents = encode_codestream(&fingerprint(200, 210), DEFAULT_BITRATE).unwrap().encode();
+        let dtt = contents.windows(2).position(|m| m == [0xFF, 0xA4]).unwrap();
+        // an even lowpass filter length fails to parse
+        contents[dtt + 4] = 8;
+
+        let mut out = vec![];
+        let error = dump(&contents, &mut out).unwrap_err();
+        let listing = String::from_utf8(out).unwrap();
+        assert!(listing.contains(&format!("{:>8}  FFA4 DTT       60  error: {}\n", dtt, error)));
+        assert!(listing.contains("FFA2 SOF"));
+        assert!(!listing.contains("FFA2 SOF        19  black"));
+        assert!(listing.contains(&format!("{:>8}  FFA1 EOI        2  \n", contents.len() - 2)));
+        assert!(listing.contains("block 3: "));
+    }
+}
@@ -26,20 +26,457 @@
 // 2.5 Abbreviated format for table-specification data
 //     This format contains only table-specification data. It is a means by which the application may install
 //     in the decoder the tables required to subsequently reconstruct one or more fingerprint images.
+//
+// The quantized coefficients of a block are coded with the symbols of Table A.2: zero runs of 1 to
+// 100, coefficients from -73 to 74 at 180 plus their value, and escapes followed by 8 or 16 raw
+// bits for longer runs and larger coefficients. Codes are written most significant bit first, an
+// X'FF' byte is followed by a stuffed zero byte and the last byte is padded with 1-bits.
 #![allow(dead_code)]
-pub mod encoder {}
+use std::io;
 
-pub mod decoder {}
+/// Symbols of the Huffman coding model, Table A.2
+pub mod symbols {
+    pub const MAX_ZERO_RUN: u8 = 100;
+    pub const POSITIVE_8_BIT: u8 = 101;
+    pub const NEGATIVE_8_BIT: u8 = 102;
+    pub const POSITIVE_16_BIT: u8 = 103;
+    pub const NEGATIVE_16_BIT: u8 = 104;
+    pub const ZERO_RUN_8_BIT: u8 = 105;
+    pub const ZERO_RUN_16_BIT: u8 = 106;
+    /// Symbol of coefficient value 0, which is never used since zeros are coded as runs.
+    pub const COEFFICIENT_OFFSET: i32 = 180;
+    pub const MIN_COEFFICIENT: i32 = -73;
+    pub const MAX_COEFFICIENT: i32 = 74;
+}
 
-#[derive(Debug)]
-struct HuffmanTable {}
+/// Most tables a decoder can hold, table identifiers run from 0 to 7.
+pub const MAX_HUFFMAN_TABLES: usize = 8;
+const MAX_CODE_LENGTH: usize = 16;
 
-#[derive(Debug)]
-pub struct CompressedData {}
+/// A Huffman table as it is specified in the compressed data, Annex C.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuffmanTable {
+    /// Number of codes of each length from 1 to 16 bits, the list BITS.
+    pub bits: [u8; MAX_CODE_LENGTH],
+    /// The symbols in order of increasing code length, the list HUFFVAL.
+    pub values: Vec<u8>,
+}
 
-#[derive(Debug)]
+impl HuffmanTable {
+    pub fn new(bits: [u8; MAX_CODE_LENGTH], values: Vec<u8>) -> io::Result<Self> {
+        let count = bits.iter().map(|b| *b as usize).sum::<usize>();
+        if count != values.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("Huffman table has {} code lengths but {} values", count, values.len())));
+        }
+        Ok(Self { bits, values })
+    }
+
+    /// Optimal code lengths for the symbol `frequencies`, limited to 16 bits, following Annex K of
+    /// ISO 10918-1. One extra code point is reserved so no code consists of 1-bits only.
+    pub fn from_frequencies(frequencies: &[u32; 256]) -> Self {
+        let mut frequency = frequencies.iter().map(|f| *f as u64).collect::<Vec<_>>();
+        frequency.push(1);
+        let mut code_size = vec![0usize; frequency.len()];
+        let mut others: Vec<Option<usize>> = vec![None; frequency.len()];
+        // the least frequent symbol, the largest one among equally frequent symbols
+        let least = |frequency: &[u64], except: Option<usize>| (0..frequency.len())
+            .filter(|v| frequency[*v] > 0 && Some(*v) != except)
+            .fold(None, |least: Option<usize>, v| match least {
+                Some(l) if frequency[l] < frequency[v] => Some(l),
+                _ => Some(v),
+            });
+        while let Some(v1) = least(&frequency, None) {
+            let Some(v2) = least(&frequency, Some(v1)) else { break };
+            frequency[v1] += frequency[v2];
+            frequency[v2] = 0;
+            // every symbol in both branches moves one level down, then v2's branch is chained
+            // onto the end of v1's
+            let mut v = v1;
+            code_size[v] += 1;
+            while let Some(next) = others[v] {
+                v = next;
+                code_size[v] += 1;
+            }
+            others[v] = Some(v2);
+            let mut v = v2;
+            code_size[v] += 1;
+            while let Some(next) = others[v] {
+                v = next;
+                code_size[v] += 1;
+            }
+        }
+
+        let mut bits = vec![0usize; code_size.iter().max().copied().unwrap_or(0).max(MAX_CODE_LENGTH) + 1];
+        for size in code_size.iter().filter(|s| **s > 0) {
+            bits[*size] += 1;
+        }
+        // Figure K.3, move codes longer than 16 bits up the tree
+        for i in (MAX_CODE_LENGTH + 1..bits.len()).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+        // give up the reserved code point, which has the longest code
+        if let Some(longest) = (1..=MAX_CODE_LENGTH).rev().find(|i| bits[*i] > 0) {
+            bits[longest] -= 1;
+        }
+
+        let mut values = vec![];
+        for size in 1..code_size.len() {
+            values.extend((0..256).filter(|v| code_size[*v] == size).map(|v| v as u8));
+        }
+        let mut table_bits = [0u8; MAX_CODE_LENGTH];
+        for (i, b) in table_bits.iter_mut().enumerate() {
+            *b = bits[i + 1] as u8;
+        }
+        Self { bits: table_bits, values }
+    }
+
+    /// Code and code length of every symbol, Figures C.1 to C.3, a length of 0 for symbols without
+    /// a code.
+    pub fn codes(&self) -> Vec<(u16, u8)> {
+        let mut codes = vec![(0, 0); 256];
+        let mut code = 0u32;
+        let mut values = self.values.iter();
+        for (i, count) in self.bits.iter().enumerate() {
+            for _ in 0..*count {
+                if let Some(value) = values.next() {
+                    codes[*value as usize] = (code as u16, i as u8 + 1);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+}
+
+pub mod encoder {
+    use super::symbols::*;
+    use super::HuffmanTable;
+
+    /// A symbol with the raw bits that follow it and their number.
+    type Symbol = (u8, u16, u8);
+
+    fn zero_run(run: usize) -> Symbol {
+        if run <= MAX_ZERO_RUN as usize {
+            (run as u8, 0, 0)
+        } else if run <= u8::MAX as usize {
+            (ZERO_RUN_8_BIT, run as u16, 8)
+        } else {
+            (ZERO_RUN_16_BIT, run as u16, 16)
+        }
+    }
+
+    fn coefficient(value: i32) -> Symbol {
+        let magnitude = value.unsigned_abs().min(u16::MAX as u32) as u16;
+        match value {
+            v if (MIN_COEFFICIENT..=MAX_COEFFICIENT).contains(&v) => ((COEFFICIENT_OFFSET + v) as u8, 0, 0),
+            v if v > 0 && magnitude <= u8::MAX as u16 => (POSITIVE_8_BIT, magnitude, 8),
+            v if v > 0 => (POSITIVE_16_BIT, magnitude, 16),
+            _ if magnitude <= u8::MAX as u16 => (NEGATIVE_8_BIT, magnitude, 8),
+            _ => (NEGATIVE_16_BIT, magnitude, 16),
+        }
+    }
+
+    /// The symbols coding `coefficients`, zero runs longer than 65535 are split.
+    pub fn symbols(coefficients: &[i32]) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        let mut run = 0;
+        for c in coefficients {
+            if *c == 0 {
+                run += 1;
+                if run == u16::MAX as usize {
+                    symbols.push(zero_run(run));
+                    run = 0;
+                }
+                continue;
+            }
+            if run > 0 {
+                symbols.push(zero_run(run));
+                run = 0;
+            }
+            symbols.push(coefficient(*c));
+        }
+        if run > 0 {
+            symbols.push(zero_run(run));
+        }
+        symbols
+    }
+
+    /// Add the occurrences of every symbol coding `coefficients` to `frequencies`.
+    pub fn count_symbols(coefficients: &[i32], frequencies: &mut [u32; 256]) {
+        for (symbol, _, _) in symbols(coefficients) {
+            frequencies[symbol as usize] += 1;
+        }
+    }
+
+    /// Number of coefficients `symbol` codes.
+    fn coefficient_count((symbol, extra, _): Symbol) -> usize {
+        match symbol {
+            1..=MAX_ZERO_RUN => symbol as usize,
+            ZERO_RUN_8_BIT | ZERO_RUN_16_BIT => extra as usize,
+            _ => 1,
+        }
+    }
+
+    /// Bits the symbols coding `coefficients` take with `table`, without stuffed bytes and
+    /// padding, split by the first coefficient of each symbol into the ranges before each of the
+    /// ascending indices `ends`.
+    pub fn bits_per_range(coefficients: &[i32], table: &HuffmanTable, ends: &[usize]) -> Vec<usize> {
+        let codes = table.codes();
+        let mut bits = vec![0; ends.len()];
+        let (mut position, mut range) = (0, 0);
+        for symbol in symbols(coefficients) {
+            while range + 1 < ends.len() && position >= ends[range] {
+                range += 1;
+            }
+            if let Some(range_bits) = bits.get_mut(range) {
+                *range_bits += (codes[symbol.0 as usize].1 + symbol.2) as usize;
+            }
+            position += coefficient_count(symbol);
+        }
+        bits
+    }
+
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        buffer: u32,
+        count: u8,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u16, length: u8) {
+            for i in (0..length).rev() {
+                self.buffer = (self.buffer << 1) | ((value >> i) & 1) as u32;
+                self.count += 1;
+                if self.count == 8 {
+                    self.push_byte();
+                }
+            }
+        }
+
+        fn push_byte(&mut self) {
+            let byte = self.buffer as u8;
+            self.bytes.push(byte);
+            if byte == 0xFF {
+                self.bytes.push(0);
+            }
+            self.buffer = 0;
+            self.count = 0;
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.count > 0 {
+                let padding = 8 - self.count;
+                self.write(u16::MAX, padding);
+            }
+            self.bytes
+        }
+    }
+
+    /// Entropy-coded data segment of `coefficients`, `table` needs a code for every symbol used.
+    pub fn encode(coefficients: &[i32], table: &HuffmanTable) -> Vec<u8> {
+        let codes = table.codes();
+        let mut writer = BitWriter::default();
+        for (symbol, extra, extra_length) in symbols(coefficients) {
+            let (code, length) = codes[symbol as usize];
+            debug_assert!(length > 0, "no Huffman code for symbol {}", symbol);
+            writer.write(code, length);
+            writer.write(extra, extra_length);
+        }
+        writer.finish()
+    }
+}
+
+pub mod decoder {
+    use std::io;
+
+    use super::symbols::*;
+    use super::{HuffmanTable, MAX_CODE_LENGTH};
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+        byte: u8,
+        count: u8,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn new(data: &'a [u8]) -> Self {
+            Self { data, position: 0, byte: 0, count: 0 }
+        }
+
+        fn bit(&mut self) -> Option<u16> {
+            if self.count == 0 {
+                self.byte = *self.data.get(self.position)?;
+                // skip the zero byte stuffed after X'FF'
+                self.position += if self.byte == 0xFF && self.data.get(self.position + 1) == Some(&0) { 2 } else { 1 };
+                self.count = 8;
+            }
+            self.count -= 1;
+            Some(((self.byte >> self.count) & 1) as u16)
+        }
+
+        fn bits(&mut self, length: u8) -> Option<u16> {
+            (0..length).try_fold(0, |value, _| Some((value << 1) | self.bit()?))
+        }
+    }
+
+    /// Lookup of the symbols by code, per code length the smallest and largest code and the index
+    /// of the first symbol with a code of that length, Figure F.15 of ISO 10918-1.
+    struct Decoder<'a> {
+        table: &'a HuffmanTable,
+        min_code: [i32; MAX_CODE_LENGTH],
+        max_code: [i32; MAX_CODE_LENGTH],
+        first_value: [usize; MAX_CODE_LENGTH],
+    }
+
+    impl<'a> Decoder<'a> {
+        fn new(table: &'a HuffmanTable) -> Self {
+            let mut decoder = Self { table, min_code: [0; MAX_CODE_LENGTH], max_code: [-1; MAX_CODE_LENGTH], first_value: [0; MAX_CODE_LENGTH] };
+            let (mut code, mut value) = (0i32, 0usize);
+            for (i, count) in table.bits.iter().enumerate() {
+                if *count > 0 {
+                    decoder.first_value[i] = value;
+                    decoder.min_code[i] = code;
+                    code += *count as i32;
+                    value += *count as usize;
+                    decoder.max_code[i] = code - 1;
+                }
+                code <<= 1;
+            }
+            decoder
+        }
+
+        /// The next symbol, `None` once only the padding is left.
+        fn symbol(&self, reader: &mut BitReader) -> io::Result<Option<u8>> {
+            let mut code = 0i32;
+            for i in 0..MAX_CODE_LENGTH {
+                let Some(bit) = reader.bit() else { return Ok(None) };
+                code = (code << 1) | bit as i32;
+                if code <= self.max_code[i] {
+                    let index = self.first_value[i] + (code - self.min_code[i]) as usize;
+                    return self.table.values.get(index).copied().map(Some)
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Huffman code without a value"));
+                }
+            }
+            Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid Huffman code"))
+        }
+    }
+
+    /// Decode the entropy-coded data segment `data`, still containing the stuffed bytes, and
+    /// append the coefficients to `coefficients`.
+    pub fn decode(data: &[u8], table: &HuffmanTable, coefficients: &mut Vec<i32>) -> io::Result<()> {
+        let decoder = Decoder::new(table);
+        let mut reader = BitReader::new(data);
+        let truncated = || io::Error::new(io::ErrorKind::InvalidData, "Entropy-coded data ends inside an escape");
+        while let Some(symbol) = decoder.symbol(&mut reader)? {
+            match symbol {
+                1..=MAX_ZERO_RUN => coefficients.extend(std::iter::repeat_n(0, symbol as usize)),
+                POSITIVE_8_BIT => coefficients.push(reader.bits(8).ok_or_else(truncated)? as i32),
+                NEGATIVE_8_BIT => coefficients.push(-(reader.bits(8).ok_or_else(truncated)? as i32)),
+                POSITIVE_16_BIT => coefficients.push(reader.bits(16).ok_or_else(truncated)? as i32),
+                NEGATIVE_16_BIT => coefficients.push(-(reader.bits(16).ok_or_else(truncated)? as i32)),
+                ZERO_RUN_8_BIT => {
+                    let run = reader.bits(8).ok_or_else(truncated)?;
+                    coefficients.extend(std::iter::repeat_n(0, run as usize));
+                }
+                ZERO_RUN_16_BIT => {
+                    let run = reader.bits(16).ok_or_else(truncated)?;
+                    coefficients.extend(std::iter::repeat_n(0, run as usize));
+                }
+                s if s as i32 != COEFFICIENT_OFFSET && (COEFFICIENT_OFFSET + MIN_COEFFICIENT..=COEFFICIENT_OFFSET + MAX_COEFFICIENT).contains(&(s as i32)) =>
+                    coefficients.push(s as i32 - COEFFICIENT_OFFSET),
+                s => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid symbol {}", s))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which blocks of an image share a Huffman table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TableSharing {
+    /// The first block gets table 0 and the others share table 1, as the FBI encoder does
+    #[default]
+    Shared,
+    /// Every block gets a table of its own
+    Separate,
+}
+
+impl TableSharing {
+    /// `Separate` if no two blocks with the table identifiers `table_ids` use the same one.
+    pub fn of(table_ids: &[u8]) -> Self {
+        let mut ids = table_ids.to_vec();
+        ids.sort();
+        ids.dedup();
+        if ids.len() == table_ids.len() { TableSharing::Separate } else { TableSharing::Shared }
+    }
+
+    /// The table identifier of each of `blocks` blocks. Separate tables are limited to the 8
+    /// identifiers, further blocks share the last.
+    pub fn table_ids(self, blocks: usize) -> Vec<u8> {
+        (0..blocks)
+            .map(|i| match self {
+                TableSharing::Shared => i.min(1) as u8,
+                TableSharing::Separate => i.min(MAX_HUFFMAN_TABLES - 1) as u8,
+            })
+            .collect()
+    }
+}
+
+/// The quantized coefficients of an image in their blocks, with the Huffman table of every block
+/// and the identifier it is installed under. Blocks with the same identifier share a table unless
+/// it is redefined between them.
+#[derive(Debug, Clone, PartialEq)]
 pub struct CompressedImageData {
-    pub data: CompressedData,
+    pub blocks: Vec<Vec<i32>>,
+    pub table_ids: Vec<u8>,
+    pub tables: Vec<HuffmanTable>,
+}
+
+impl CompressedImageData {
+    /// The `blocks` with tables built for their symbol frequencies, one per identifier `sharing`
+    /// assigns.
+    pub fn new(blocks: Vec<Vec<i32>>, sharing: TableSharing) -> Self {
+        let table_ids = sharing.table_ids(blocks.len());
+        Self::with_table_ids(blocks, table_ids)
+    }
+
+    /// The `blocks` with tables built for their symbol frequencies, one per identifier in
+    /// `table_ids`, which must be below `MAX_HUFFMAN_TABLES`.
+    pub fn with_table_ids(blocks: Vec<Vec<i32>>, table_ids: Vec<u8>) -> Self {
+        let mut frequencies = [[0; 256]; MAX_HUFFMAN_TABLES];
+        for (coefficients, id) in blocks.iter().zip(&table_ids) {
+            encoder::count_symbols(coefficients, &mut frequencies[*id as usize]);
+        }
+        let tables = table_ids.iter().map(|id| HuffmanTable::from_frequencies(&frequencies[*id as usize])).collect();
+        Self { blocks, table_ids, tables }
+    }
+
+    pub fn sharing(&self) -> TableSharing {
+        TableSharing::of(&self.table_ids)
+    }
+
+    /// The coefficients of all blocks, in order.
+    pub fn coefficients(&self) -> Vec<i32> {
+        self.blocks.concat()
+    }
+
+    /// The entropy-coded data segment of every block.
+    pub fn encode(&self) -> Vec<Vec<u8>> {
+        self.blocks.iter().zip(&self.tables).map(|(coefficients, table)| encoder::encode(coefficients, table)).collect()
+    }
 }
 
 #[derive(Debug)]
@@ -52,23 +489,139 @@ enum AbbreviatedFormat {
 
 pub mod markers {
     // start of image
-    const SOI: &[u8] = &[0xFFu8, 0xA0u8];
+    pub const SOI: u16 = 0xFFA0;
     // End of image
-    const EOI: &[u8] = &[0xFFu8, 0xA1u8];
+    pub const EOI: u16 = 0xFFA1;
     // Start of frame
-    const SOF: &[u8] = &[0xFFu8, 0xA2u8];
+    pub const SOF: u16 = 0xFFA2;
     // Start of block
-    const SOB: &[u8] = &[0xFFu8, 0xA3u8];
+    pub const SOB: u16 = 0xFFA3;
     // Define transform table
-    const DTT: &[u8] = &[0xFFu8, 0xA4u8];
+    pub const DTT: u16 = 0xFFA4;
     // Define quantization table
-    const DQT: &[u8] = &[0xFFu8, 0xA5u8];
+    pub const DQT: u16 = 0xFFA5;
     // Define Huffman tables(s)
-    const DHT: &[u8] = &[0xFFu8, 0xA6u8];
+    pub const DHT: u16 = 0xFFA6;
     // Define restart interval
-    const DRI: &[u8] = &[0xFFu8,  0xA7u8];
+    pub const DRI: u16 = 0xFFA7;
     //  Restart with modulo 8 count “m”, here set to 0
-    const RST_M: &[u8] = &[0xFFu8, 0xB0u8];
+    pub const RST_M: u16 = 0xFFB0;
     // Comment
-    const COM: &[u8] = &[0xFFu8, 0xA8u8];
+    pub const COM: u16 = 0xFFA8;
+
+    /// The mnemonic of a marker, `None` for markers the standard does not define.
+    pub fn name(marker: u16) -> Option<String> {
+        Some(match marker {
+            SOI => "SOI".to_string(),
+            EOI => "EOI".to_string(),
+            SOF => "SOF".to_string(),
+            SOB => "SOB".to_string(),
+            DTT => "DTT".to_string(),
+            DQT => "DQT".to_string(),
+            DHT => "DHT".to_string(),
+            DRI => "DRI".to_string(),
+            COM => "COM".to_string(),
+            m if (RST_M..RST_M + 8).contains(&m) => format!("RST{}", m - RST_M),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decoder, encoder, CompressedImageData, HuffmanTable, TableSharing};
+
+    fn table_for(coefficients: &[i32]) -> HuffmanTable {
+        let mut frequencies = [0; 256];
+        encoder::count_symbols(coefficients, &mut frequencies);
+        HuffmanTable::from_frequencies(&frequencies)
+    }
+
+    #[test]
+    fn test_code_lengths() {
+        let mut frequencies = [0; 256];
+        frequencies[1] = 100;
+        frequencies[2] = 50;
+        frequencies[3] = 25;
+        frequencies[180] = 25;
+        let table = HuffmanTable::from_frequencies(&frequencies);
+        assert_eq!([1, 1, 1, 1], table.bits[..4]);
+        assert_eq!(vec![1, 2, 3, 180], table.values);
+        let codes = table.codes();
+        assert_eq!((0b0, 1), codes[1]);
+        assert_eq!((0b1110, 4), codes[180]);
+    }
+
+    #[test]
+    fn test_lengths_limited() {
+        // Fibonacci frequencies give the most unbalanced tree
+        let mut frequencies = [0; 256];
+        let (mut a, mut b) = (1, 1);
+        for f in frequencies.iter_mut().take(30) {
+            *f = a;
+            (a, b) = (b, a + b);
+        }
+        let table = HuffmanTable::from_frequencies(&frequencies);
+        assert_eq!(30, table.values.len());
+        assert_eq!(30, table.bits.iter().map(|b| *b as usize).sum::<usize>());
+        let codes = table.codes();
+        assert!(codes.iter().all(|(code, length)| *length == 0 || code.count_ones() < *length as u32));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut coefficients = vec![0, 0, 1, -1, 74, -73, 75, -74, 255, -256, 1000, -40000, 5];
+        coefficients.extend(std::iter::repeat_n(0, 100));
+        coefficients.push(3);
+        coefficients.extend(std::iter::repeat_n(0, 255));
+        coefficients.push(-3);
+        coefficients.extend(std::iter::repeat_n(0, 70000));
+        let table = table_for(&coefficients);
+        let data = encoder::encode(&coefficients, &table);
+        let mut decoded = vec![];
+        decoder::decode(&data, &table, &mut decoded).unwrap();
+        assert_eq!(coefficients, decoded);
+
+        // the leading run of 2 zeros alone, up to the run of 100 zeros, and the rest
+        let split = encoder::bits_per_range(&coefficients, &table, &[2, 13, coefficients.len()]);
+        let whole = encoder::bits_per_range(&coefficients, &table, &[coefficients.len()]);
+        assert_eq!(table.codes()[2].1 as usize, split[0]);
+        assert_eq!(whole[0], split.iter().sum::<usize>());
+        assert!(whole[0] <= data.len() * 8 && whole[0] > data.len() * 8 - 16);
+    }
+
+    #[test]
+    fn test_byte_stuffing() {
+        // a run of the least frequent symbol gives the all 1 prefix codes and so X'FF' bytes
+        let mut coefficients = vec![1; 64];
+        coefficients.extend([2, 3, 4, 5, 6, 7, 8].repeat(4));
+        let table = table_for(&coefficients);
+        let data = encoder::encode(&coefficients, &table);
+        assert!(data.windows(2).all(|w| w[0] != 0xFF || w[1] == 0));
+        let mut decoded = vec![];
+        decoder::decode(&data, &table, &mut decoded).unwrap();
+        assert_eq!(coefficients, decoded);
+    }
+
+    #[test]
+    fn test_table_sharing() {
+        let blocks = vec![vec![5, 0, 0, -3, 7], vec![1, 1, 0, 2], vec![0, 0, 0, 0, 90, -1]];
+        let shared = CompressedImageData::new(blocks.clone(), TableSharing::Shared);
+        assert_eq!(vec![0, 1, 1], shared.table_ids);
+        assert_eq!(shared.tables[1], shared.tables[2]);
+        assert_eq!(TableSharing::Shared, shared.sharing());
+        let separate = CompressedImageData::new(blocks.clone(), TableSharing::Separate);
+        assert_eq!(vec![0, 1, 2], separate.table_ids);
+        assert_ne!(separate.tables[1], separate.tables[2]);
+        assert_eq!(TableSharing::Separate, separate.sharing());
+
+        for image_data in [shared, separate] {
+            for ((data, table), coefficients) in image_data.encode().iter().zip(&image_data.tables).zip(&blocks) {
+                let mut decoded = vec![];
+                decoder::decode(data, table, &mut decoded).unwrap();
+                assert_eq!(coefficients, &decoded);
+            }
+            assert_eq!(blocks.concat(), image_data.coefficients());
+        }
+    }
 }
@@ -0,0 +1,114 @@
+//! Module for the 8-bit grey scale images WSQ is defined on
+//
+// The encoder works on the pixels shifted by their mean and scaled into roughly -128 to 128, the
+// decoder undoes this and converts back to pixels by rounding halves up and clipping to 0 to 255:
+//     pixel = min(max(⌊â R + M + 0.5⌋, 0), 255)
+use std::io;
+
+use crate::pnm;
+use crate::swt::{FloatImage, Sample};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrayImage {
+    pub data: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    /// Scan resolution in pixels per inch, when known.
+    pub ppi: Option<u16>,
+}
+
+impl GrayImage {
+    pub fn new(width: usize, height: usize, data: Vec<u8>) -> io::Result<Self> {
+        if width.checked_mul(height) != Some(data.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} pixels do not make a {}x{} image", data.len(), width, height)));
+        }
+        Ok(Self { data, width, height, ppi: None })
+    }
+
+    pub fn with_ppi(mut self, ppi: u16) -> Self {
+        self.ppi = Some(ppi);
+        self
+    }
+
+    /// The pixels as samples, with the minimum and maximum value set to the darkest and brightest
+    /// pixel, which is what the encoder derives the rescale factor from.
+    pub fn to_float_image<F: Sample>(&self) -> FloatImage<F> {
+        let mut image = FloatImage {
+            data: self.data.iter().map(|p| F::from_i32(*p as i32)).collect(),
+            width: self.width,
+            height: self.height,
+            min_value: F::default(),
+            max_value: F::default(),
+        };
+        image.find_and_set_min_max();
+        image
+    }
+
+    /// Round and clip decoded samples, which are already de-normalized, to pixels.
+    pub fn from_float_image<F: Sample>(image: &FloatImage<F>) -> Self {
+        let data = image.data.iter().map(|f| f.round_half_up().clamp(0, 255) as u8).collect();
+        Self { data, width: image.width, height: image.height, ppi: None }
+    }
+}
+
+/// Reduce deeper images to 8 bits, scaling `max_value` to 255.
+impl From<&pnm::Image> for GrayImage {
+    fn from(value: &pnm::Image) -> Self {
+        let max_value = value.max_value as u32;
+        let data = value.data.iter().map(|s| ((*s as u32 * 255 + max_value / 2) / max_value) as u8).collect();
+        Self { data, width: value.width, height: value.height, ppi: None }
+    }
+}
+
+impl From<&GrayImage> for pnm::Image {
+    fn from(value: &GrayImage) -> Self {
+        pnm::Image {
+            data: value.data.iter().map(|p| *p as u16).collect(),
+            width: value.width,
+            height: value.height,
+            max_value: 255,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GrayImage;
+    use crate::pnm;
+    use crate::swt::FloatImage;
+    use crate::swt::fixed::Fixed;
+    use crate::swt::Sample;
+
+    #[test]
+    fn test_new_checks_size() {
+        assert!(GrayImage::new(3, 2, vec![0; 6]).is_ok());
+        assert!(GrayImage::new(3, 2, vec![0; 5]).is_err());
+        assert!(GrayImage::new(usize::MAX, 2, vec![0; 2]).is_err());
+    }
+
+    #[test]
+    fn test_rounding_and_clipping() {
+        let data = vec![-3.2, -0.5, 0.49, 0.5, 127.5, 254.5, 255.49, 300.];
+        let image = FloatImage { data, width: 8, height: 1, min_value: 0., max_value: 255. };
+        let expected = vec![0, 0, 0, 1, 128, 255, 255, 255];
+        assert_eq!(expected, GrayImage::from_float_image(&image).data);
+        assert_eq!(expected, GrayImage::from_float_image(&image.convert::<f32>()).data);
+        assert_eq!(expected, GrayImage::from_float_image(&image.convert::<Fixed>()).data);
+    }
+
+    #[test]
+    fn test_to_float_image() {
+        let image = GrayImage::new(2, 2, vec![10, 20, 30, 240]).unwrap();
+        let float_image = image.to_float_image::<f64>();
+        assert_eq!((10., 240.), (float_image.min_value, float_image.max_value));
+        assert_eq!(image, GrayImage::from_float_image(&float_image));
+        assert_eq!(vec![10., 20., 30., 240.], float_image.data.iter().map(|f| f.to_f64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_from_16_bit() {
+        let deep = pnm::Image { data: vec![0, 128, 32768, 65535], width: 4, height: 1, max_value: 65535 };
+        assert_eq!(vec![0, 0, 128, 255], GrayImage::from(&deep).data);
+    }
+}
@@ -0,0 +1,97 @@
+//! Module for PNG, BMP and TIFF images through the `image` crate, only built with the `image-io` feature
+//
+// Colour images are reduced to their luminance and 16-bit samples to 8 bits, both the way the
+// `image` crate converts to `Luma<u8>`. The scan resolution these formats may carry is not read.
+use std::io;
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageFormat};
+
+use crate::gray::GrayImage;
+
+fn invalid_data(error: image::ImageError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl From<&image::GrayImage> for GrayImage {
+    fn from(value: &image::GrayImage) -> Self {
+        Self { data: value.as_raw().clone(), width: value.width() as usize, height: value.height() as usize, ppi: None }
+    }
+}
+
+impl From<&DynamicImage> for GrayImage {
+    fn from(value: &DynamicImage) -> Self {
+        match value {
+            DynamicImage::ImageLuma8(gray) => Self::from(gray),
+            _ => Self::from(&value.to_luma8()),
+        }
+    }
+}
+
+impl From<&GrayImage> for image::GrayImage {
+    fn from(value: &GrayImage) -> Self {
+        image::GrayImage::from_raw(value.width as u32, value.height as u32, value.data.clone())
+            .expect("GrayImage holds width times height pixels")
+    }
+}
+
+impl From<&GrayImage> for DynamicImage {
+    fn from(value: &GrayImage) -> Self {
+        DynamicImage::ImageLuma8(value.into())
+    }
+}
+
+/// Decode a PNG, BMP or TIFF image, recognised by its contents.
+pub fn parse(contents: &[u8]) -> io::Result<GrayImage> {
+    let format = image::guess_format(contents).map_err(invalid_data)?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Bmp | ImageFormat::Tiff) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} images are not supported", format)));
+    }
+    image::load_from_memory_with_format(contents, format).map(|image| GrayImage::from(&image)).map_err(invalid_data)
+}
+
+pub fn encode_png(image: &GrayImage) -> io::Result<Vec<u8>> {
+    let mut contents = Cursor::new(vec![]);
+    image::GrayImage::from(image).write_to(&mut contents, ImageFormat::Png).map_err(invalid_data)?;
+    Ok(contents.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, ImageBuffer, ImageFormat, Luma, Rgb};
+
+    use super::{encode_png, parse};
+    use crate::gray::GrayImage;
+
+    fn encode(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut contents = Cursor::new(vec![]);
+        image.write_to(&mut contents, format).unwrap();
+        contents.into_inner()
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let image = GrayImage::new(3, 2, vec![0, 50, 100, 150, 200, 255]).unwrap();
+        assert_eq!(image, parse(&encode_png(&image).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_luminance() {
+        let rgb = ImageBuffer::from_fn(3, 1, |x, _| [Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([77, 77, 77])][x as usize]);
+        let image = parse(&encode(&DynamicImage::ImageRgb8(rgb), ImageFormat::Bmp)).unwrap();
+        assert_eq!((3, 1), (image.width, image.height));
+        assert!(image.data[1] > image.data[0]);
+        assert_eq!(77, image.data[2]);
+    }
+
+    #[test]
+    fn test_16_bit() {
+        let deep = ImageBuffer::from_raw(3, 1, vec![0u16, 32896, 65535]).map(DynamicImage::ImageLuma16).unwrap();
+        let image = parse(&encode(&deep, ImageFormat::Tiff)).unwrap();
+        assert_eq!(vec![0, 128, 255], image.data);
+        let gray: ImageBuffer<Luma<u8>, Vec<u8>> = (&image).into();
+        assert_eq!(image, GrayImage::from(&gray));
+    }
+}
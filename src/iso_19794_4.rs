@@ -0,0 +1,358 @@
+//! Module for ISO/IEC 19794-4 finger image records
+//
+// A 32 byte general header followed by one representation per finger view, all numbers unsigned
+// and most significant byte first:
+//     format identifier          4 bytes  "FIR\0"
+//     version                    4 bytes  "010\0"
+//     record length              6 bytes  headers included
+//     capture device ID          2 bytes
+//     image acquisition level    2 bytes
+//     number of fingers          1 byte
+//     scale units                1 byte   1 for pixels per inch, 2 for pixels per centimeter
+//     scan resolution            2 + 2    horizontal and vertical
+//     image resolution           2 + 2    horizontal and vertical
+//     pixel depth                1 byte   bits per pixel
+//     compression algorithm      1 byte   2 for WSQ
+//     reserved                   2 bytes
+// Each view has a 14 byte header before its image data:
+//     length                     4 bytes  header included
+//     finger position            1 byte
+//     count of views             1 byte
+//     view number                1 byte
+//     image quality              1 byte
+//     impression type            1 byte
+//     horizontal line length     2 bytes  the width in pixels
+//     vertical line length       2 bytes  the height in pixels
+//     reserved                   1 byte
+use std::io;
+
+use crate::ansi_nist::type14::{self, Type14Record};
+use crate::codec;
+use crate::gray::GrayImage;
+
+pub const FORMAT_IDENTIFIER: &[u8; 4] = b"FIR\0";
+pub const VERSION: &[u8; 4] = b"010\0";
+pub const HEADER_LEN: usize = 32;
+pub const VIEW_HEADER_LEN: usize = 14;
+pub const PIXELS_PER_INCH: u8 = 1;
+pub const PIXELS_PER_CENTIMETER: u8 = 2;
+/// Compression algorithm of uncompressed 8-bit pixels
+pub const UNCOMPRESSED: u8 = 0;
+/// Compression algorithm of WSQ compressed data
+pub const WSQ: u8 = 2;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(contents: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([contents[offset], contents[offset + 1]])
+}
+
+/// The image acquisition level of the settings closest to a resolution.
+fn acquisition_level(ppi: u16) -> u16 {
+    match ppi {
+        0..=249 => 10,
+        250..=499 => 20,
+        500..=999 => 30,
+        _ => 40,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerView {
+    pub finger_position: u8,
+    pub view_count: u8,
+    pub view_number: u8,
+    pub quality: u8,
+    pub impression_type: u8,
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+impl FingerView {
+    pub fn encoded_len(&self) -> usize {
+        VIEW_HEADER_LEN + self.data.len()
+    }
+}
+
+/// The resolution all views share, the record header holds only one.
+fn shared_ppi(ppis: impl Iterator<Item=io::Result<u16>>) -> io::Result<u16> {
+    let ppis = ppis.collect::<io::Result<Vec<_>>>()?;
+    match ppis.first() {
+        Some(first) if ppis.iter().any(|ppi| ppi != first) =>
+            Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Views of {:?} pixels per inch can not share a record", ppis))),
+        first => Ok(first.copied().unwrap_or(type14::DEFAULT_PPI)),
+    }
+}
+
+/// A width or height, which the record stores in 16 bits.
+fn dimension(pixels: usize) -> io::Result<u16> {
+    u16::try_from(pixels).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} pixels exceed the 65535 of a view", pixels)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerImageRecord {
+    pub capture_device: u16,
+    pub acquisition_level: u16,
+    pub scale_units: u8,
+    pub scan_resolution: (u16, u16),
+    pub image_resolution: (u16, u16),
+    pub pixel_depth: u8,
+    pub compression: u8,
+    pub views: Vec<FingerView>,
+}
+
+impl FingerImageRecord {
+    /// A record of `images` compressed to WSQ at `bitrate`, for unknown fingers. The images must
+    /// share their resolution.
+    pub fn from_images(images: &[GrayImage], bitrate: f64) -> io::Result<Self> {
+        let ppi = shared_ppi(images.iter().map(|image| Ok(image.ppi.unwrap_or(type14::DEFAULT_PPI))))?;
+        let views = images.iter()
+            .map(|image| Ok(FingerView {
+                finger_position: 0,
+                view_count: 1,
+                view_number: 1,
+                quality: 0,
+                impression_type: 0,
+                width: dimension(image.width)?,
+                height: dimension(image.height)?,
+                data: codec::encode(image, bitrate)?,
+            }))
+            .collect::<io::Result<_>>()?;
+        Ok(Self::new(ppi, WSQ, views))
+    }
+
+    fn new(ppi: u16, compression: u8, views: Vec<FingerView>) -> Self {
+        Self {
+            capture_device: 0,
+            acquisition_level: acquisition_level(ppi),
+            scale_units: PIXELS_PER_INCH,
+            scan_resolution: (ppi, ppi),
+            image_resolution: (ppi, ppi),
+            pixel_depth: 8,
+            compression,
+            views,
+        }
+    }
+
+    /// The horizontal image resolution in pixels per inch.
+    pub fn ppi(&self) -> Option<u16> {
+        match self.scale_units {
+            PIXELS_PER_INCH => Some(self.image_resolution.0),
+            PIXELS_PER_CENTIMETER => Some((self.image_resolution.0 as f64 * 2.54).round() as u16),
+            _ => None,
+        }
+    }
+
+    /// The image of the view at `index`, decompressed when it is WSQ.
+    pub fn to_image(&self, index: usize) -> io::Result<GrayImage> {
+        let view = self.views.get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("The record has no view {}", index)))?;
+        let (width, height) = (view.width as usize, view.height as usize);
+        let mut image = match (self.compression, self.pixel_depth) {
+            (UNCOMPRESSED, 8) => GrayImage::new(width, height, view.data.clone()).map_err(|e| invalid_data(e.to_string()))?,
+            (WSQ, _) => codec::decode(&view.data)?,
+            (compression, depth) => return Err(io::Error::new(io::ErrorKind::Unsupported,
+                format!("Compression algorithm {} at {} bits per pixel is not supported", compression, depth))),
+        };
+        if (image.width, image.height) != (width, height) {
+            return Err(invalid_data(format!("View is {}x{} but its image is {}x{}", width, height, image.width, image.height)));
+        }
+        image.ppi = self.ppi().or(image.ppi);
+        Ok(image)
+    }
+
+    /// The view at `index` as a Type-14 record from `source_agency`, keeping its data as it is.
+    pub fn to_type14(&self, index: usize, idc: u8, source_agency: &str) -> io::Result<Type14Record> {
+        let view = self.views.get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("The record has no view {}", index)))?;
+        let compression = match (self.compression, self.pixel_depth) {
+            (UNCOMPRESSED, 8) => type14::UNCOMPRESSED,
+            (WSQ, _) => type14::WSQ,
+            (compression, depth) => return Err(io::Error::new(io::ErrorKind::Unsupported,
+                format!("Compression algorithm {} at {} bits per pixel is not supported", compression, depth))),
+        };
+        let ppi = self.ppi().unwrap_or(type14::DEFAULT_PPI);
+        Ok(Type14Record::new(idc, source_agency, view.width as usize, view.height as usize, ppi, compression, view.data.clone())?
+            .with_finger_position(view.finger_position)
+            .with_impression_type(view.impression_type))
+    }
+
+    /// A record of the views in Type-14 `records`, which must share their compression and resolution.
+    pub fn from_type14(records: &[Type14Record]) -> io::Result<Self> {
+        let first = records.first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No Type-14 records".to_string()))?;
+        let compression = match first.compression()?.as_str() {
+            type14::UNCOMPRESSED => UNCOMPRESSED,
+            type14::WSQ | "WSQ" => WSQ,
+            cga => return Err(io::Error::new(io::ErrorKind::Unsupported, format!("Compression algorithm {} is not supported", cga))),
+        };
+        let mut views = vec![];
+        for record in records {
+            if record.compression()? != first.compression()? {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Type-14 records use different compression".to_string()));
+            }
+            views.push(FingerView {
+                finger_position: record.finger_position()?,
+                view_count: 1,
+                view_number: 1,
+                quality: 0,
+                impression_type: record.impression_type()?,
+                width: dimension(record.width()?)?,
+                height: dimension(record.height()?)?,
+                data: record.data().to_vec(),
+            });
+        }
+        let ppi = shared_ppi(records.iter().map(|record| Ok(record.ppi()?.unwrap_or(type14::DEFAULT_PPI))))?;
+        Ok(Self::new(ppi, compression, views))
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.views.iter().map(FingerView::encoded_len).sum::<usize>()
+    }
+
+    pub fn parse(contents: &[u8]) -> io::Result<Self> {
+        let header = contents.get(..HEADER_LEN)
+            .ok_or_else(|| invalid_data(format!("A finger image record needs {} header bytes, found {}", HEADER_LEN, contents.len())))?;
+        if &header[..4] != FORMAT_IDENTIFIER {
+            return Err(invalid_data("Not a finger image record".to_string()));
+        }
+        if &header[4..8] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                                      format!("Version {} is not supported", String::from_utf8_lossy(&header[4..7]))));
+        }
+        let len = header[8..14].iter().fold(0usize, |len, byte| len << 8 | *byte as usize);
+        if len < HEADER_LEN || len > contents.len() {
+            return Err(invalid_data(format!("Record length {} out of range", len)));
+        }
+        let mut views = vec![];
+        let mut position = HEADER_LEN;
+        while position < len {
+            let view = contents.get(position..position + VIEW_HEADER_LEN).filter(|_| position + VIEW_HEADER_LEN <= len)
+                .ok_or_else(|| invalid_data(format!("Truncated view header at byte {}", position)))?;
+            let view_len = u32::from_be_bytes([view[0], view[1], view[2], view[3]]) as usize;
+            if view_len < VIEW_HEADER_LEN || position + view_len > len {
+                return Err(invalid_data(format!("View length {} out of range", view_len)));
+            }
+            views.push(FingerView {
+                finger_position: view[4],
+                view_count: view[5],
+                view_number: view[6],
+                quality: view[7],
+                impression_type: view[8],
+                width: u16_at(view, 9),
+                height: u16_at(view, 11),
+                data: contents[position + VIEW_HEADER_LEN..position + view_len].to_vec(),
+            });
+            position += view_len;
+        }
+        if views.len() != header[18] as usize {
+            return Err(invalid_data(format!("Record declares {} fingers but holds {} views", header[18], views.len())));
+        }
+        Ok(Self {
+            capture_device: u16_at(header, 14),
+            acquisition_level: u16_at(header, 16),
+            scale_units: header[19],
+            scan_resolution: (u16_at(header, 20), u16_at(header, 22)),
+            image_resolution: (u16_at(header, 24), u16_at(header, 26)),
+            pixel_depth: header[28],
+            compression: header[29],
+            views,
+        })
+    }
+
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let count = u8::try_from(self.views.len()).map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidInput, format!("{} views exceed the 255 of a record", self.views.len())))?;
+        let mut contents = Vec::with_capacity(self.encoded_len());
+        contents.extend_from_slice(FORMAT_IDENTIFIER);
+        contents.extend_from_slice(VERSION);
+        contents.extend_from_slice(&(self.encoded_len() as u64).to_be_bytes()[2..]);
+        contents.extend_from_slice(&self.capture_device.to_be_bytes());
+        contents.extend_from_slice(&self.acquisition_level.to_be_bytes());
+        contents.extend([count, self.scale_units]);
+        for value in [self.scan_resolution.0, self.scan_resolution.1, self.image_resolution.0, self.image_resolution.1] {
+            contents.extend_from_slice(&value.to_be_bytes());
+        }
+        contents.extend([self.pixel_depth, self.compression, 0, 0]);
+        for view in &self.views {
+            contents.extend_from_slice(&(view.encoded_len() as u32).to_be_bytes());
+            contents.extend([view.finger_position, view.view_count, view.view_number, view.quality, view.impression_type]);
+            contents.extend_from_slice(&view.width.to_be_bytes());
+            contents.extend_from_slice(&view.height.to_be_bytes());
+            contents.push(0);
+            contents.extend_from_slice(&view.data);
+        }
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{FingerImageRecord, FingerView, PIXELS_PER_CENTIMETER, UNCOMPRESSED, WSQ};
+    use crate::ansi_nist::type14::{self, Type14Record};
+    use crate::codec::tests::fingerprint;
+    use crate::codec::DEFAULT_BITRATE;
+
+    #[test]
+    fn test_headers() {
+        let record = FingerImageRecord {
+            capture_device: 7,
+            acquisition_level: 31,
+            scale_units: PIXELS_PER_CENTIMETER,
+            scan_resolution: (197, 197),
+            image_resolution: (197, 197),
+            pixel_depth: 8,
+            compression: UNCOMPRESSED,
+            views: vec![FingerView {
+                finger_position: 2, view_count: 1, view_number: 1, quality: 80, impression_type: 0,
+                width: 2, height: 1, data: vec![10, 20],
+            }],
+        };
+        let contents = record.encode().unwrap();
+        assert_eq!(b"FIR\x00010\x00\0\0\0\0\0\x30\0\x07\0\x1f\x01\x02\0\xc5\0\xc5\0\xc5\0\xc5\x08\0\0\0", &contents[..32]);
+        assert_eq!([0, 0, 0, 16, 2, 1, 1, 80, 0, 0, 2, 0, 1, 0, 10, 20], contents[32..]);
+        assert_eq!(record, FingerImageRecord::parse(&contents).unwrap());
+        assert_eq!(Some(500), record.to_image(0).unwrap().ppi);
+        assert!(FingerImageRecord::parse(&contents[..40]).is_err());
+
+        let crowded = FingerImageRecord { views: vec![record.views[0].clone(); 256], ..record };
+        assert_eq!(io::ErrorKind::InvalidInput, crowded.encode().unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_wsq_and_type14() {
+        let images = [fingerprint(300, 310).with_ppi(1000), fingerprint(280, 260).with_ppi(1000)];
+        let mut record = FingerImageRecord::from_images(&images, DEFAULT_BITRATE).unwrap();
+        record.views[1].finger_position = 7;
+        let parsed = FingerImageRecord::parse(&record.encode().unwrap()).unwrap();
+        assert_eq!((WSQ, 40, 2), (parsed.compression, parsed.acquisition_level, parsed.views.len()));
+        let decoded = parsed.to_image(1).unwrap();
+        assert_eq!((280, 260, Some(1000)), (decoded.width, decoded.height, decoded.ppi));
+
+        let type14 = [parsed.to_type14(0, 1, "NY0303000").unwrap(), parsed.to_type14(1, 2, "NY0303000").unwrap()];
+        assert_eq!(7, type14[1].finger_position().unwrap());
+        assert_eq!((300, 310, Some(1000)), {
+            let image = type14[0].to_image().unwrap();
+            (image.width, image.height, image.ppi)
+        });
+        assert_eq!(parsed, FingerImageRecord::from_type14(&type14).unwrap());
+    }
+
+    #[test]
+    fn test_mixed_resolutions() {
+        let images = [fingerprint(64, 64).with_ppi(1000), fingerprint(64, 64)];
+        assert_eq!(io::ErrorKind::InvalidInput, FingerImageRecord::from_images(&images, DEFAULT_BITRATE).unwrap_err().kind());
+        let type14 = [Type14Record::from_image(&images[0], 1, "NY0303000", DEFAULT_BITRATE).unwrap(),
+                      Type14Record::from_image(&images[1], 2, "NY0303000", DEFAULT_BITRATE).unwrap()];
+        assert_eq!(io::ErrorKind::InvalidInput, FingerImageRecord::from_type14(&type14).unwrap_err().kind());
+
+        let mut wide = type14[0].clone();
+        wide.record.set(type14::HLL, "70000");
+        assert_eq!(io::ErrorKind::InvalidInput, FingerImageRecord::from_type14(&[wide]).unwrap_err().kind());
+    }
+}
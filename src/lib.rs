@@ -1,4 +1,15 @@
 pub mod quantization;
 pub mod entropy;
+pub mod codestream;
+pub mod codec;
+pub mod ansi_nist;
+pub mod iso_19794_4;
+pub mod pnm;
+pub mod raw;
+pub mod gray;
+pub mod metrics;
+pub mod certification;
+#[cfg(feature = "image-io")]
+pub mod image_io;
 
-pub mod swt;
\ No newline at end of file
+pub mod swt;
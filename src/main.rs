@@ -1,79 +1,490 @@
-use std::{env, fs, io};
-use netpbm;
-use wsq::swt;
-use wsq::swt::{Analysis, Synthesis};
-use wsq::swt::filter::Filter;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
 
-fn parse_args() -> Result<(String, String), Box<dyn std::error::Error>> {
-    let mut args = env::args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Not enough arguments")));
+use clap::{Args, Parser, Subcommand};
+use wsq::codec::{self, DEFAULT_BITRATE};
+use wsq::codestream::{Codestream, TransformTable};
+use wsq::gray::GrayImage;
+use wsq::quantization::QuantizationTable;
+use wsq::swt::TwoChannelSubbandCoder;
+use wsq::{certification, metrics, pnm, raw};
+
+mod batch;
+#[cfg(feature = "viewer")]
+mod viewer;
+
+#[derive(Parser)]
+#[command(name = "wsq", version, about = "WSQ fingerprint image compression")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress a grey scale image to WSQ
+    Encode(EncodeArgs),
+    /// Decompress a WSQ file to an image
+    Decode(DecodeArgs),
+    /// Compress a WSQ file again at a lower bitrate without decompressing it to pixels
+    Requantize {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Target bits per pixel
+        #[arg(short, long)]
+        bitrate: f64,
+    },
+    /// Print the frame header, tables and comments of a WSQ file
+    Info {
+        input: PathBuf,
+    },
+    /// Print how far an image is from another, either may be WSQ compressed
+    Compare {
+        original: PathBuf,
+        other: PathBuf,
+        #[command(flatten)]
+        image: ImageArgs,
+    },
+    /// List every marker segment of a WSQ file with its offset, length and parameters
+    Dump {
+        input: PathBuf,
+    },
+    /// Check the encoder and decoder against a directory of reference images, Annex AA
+    Certify {
+        directory: PathBuf,
+        /// Bitrate for reference files whose NIST comment records none
+        #[arg(short, long, default_value_t = DEFAULT_BITRATE)]
+        bitrate: f64,
+    },
+    /// Show an image, its subbands and its WSQ reconstruction in SDL windows
+    #[cfg(feature = "viewer")]
+    View {
+        input: PathBuf,
+        #[command(flatten)]
+        image: ImageArgs,
+        #[arg(short, long, default_value_t = DEFAULT_BITRATE)]
+        bitrate: f64,
+    },
+}
+
+/// How to read an input image
+#[derive(Args)]
+struct ImageArgs {
+    /// Width of a headerless raw input image
+    #[arg(long, requires = "height")]
+    width: Option<usize>,
+    /// Height of a headerless raw input image
+    #[arg(long, requires = "width")]
+    height: Option<usize>,
+    /// Scan resolution, overriding the one of the input
+    #[arg(long)]
+    ppi: Option<u16>,
+}
+
+/// Converting a directory tree instead of a single file
+#[derive(Args)]
+struct BatchArgs {
+    /// Convert every file below the input directory into the output directory
+    #[arg(short, long, requires = "output_dir")]
+    recursive: bool,
+    /// Files converted in parallel with --recursive, 0 for one per CPU
+    #[arg(short, long, default_value_t = 1, requires = "recursive")]
+    jobs: usize,
+}
+
+#[derive(Args)]
+struct EncodeArgs {
+    /// PGM, raw, PNG, BMP or TIFF image, or a directory with --recursive
+    input: PathBuf,
+    /// Output directory of --recursive
+    #[arg(requires = "recursive")]
+    output_dir: Option<PathBuf>,
+    #[arg(short, long, required_unless_present = "recursive", conflicts_with = "recursive")]
+    output: Option<PathBuf>,
+    /// Target bits per pixel
+    #[arg(short, long, default_value_t = DEFAULT_BITRATE)]
+    bitrate: f64,
+    /// Use the bin widths of this WSQ file instead of ones for --bitrate
+    #[arg(long, conflicts_with = "bitrate")]
+    table_from: Option<PathBuf>,
+    #[command(flatten)]
+    image: ImageArgs,
+    /// Also write the subbands of the decomposition as a PGM image
+    #[arg(long, conflicts_with = "recursive")]
+    subbands: Option<PathBuf>,
+    /// Also write the variance, bin widths, nonzero coefficients and bits of every subband
+    #[arg(long, conflicts_with = "recursive")]
+    statistics: Option<PathBuf>,
+    #[command(flatten)]
+    batch: BatchArgs,
+}
+
+#[derive(Args)]
+struct DecodeArgs {
+    /// WSQ file, or a directory with --recursive
+    input: PathBuf,
+    /// Output directory of --recursive
+    #[arg(requires = "recursive")]
+    output_dir: Option<PathBuf>,
+    /// PGM, raw or PNG image
+    #[arg(short, long, required_unless_present = "recursive", conflicts_with = "recursive")]
+    output: Option<PathBuf>,
+    /// Image format written, by default the one of the output's extension, PGM with --recursive
+    /// or to the standard output
+    #[arg(long, value_parser = ["pgm", "raw", "png"])]
+    format: Option<String>,
+    #[command(flatten)]
+    batch: BatchArgs,
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().is_some_and(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+fn is_raw(path: &Path) -> bool {
+    has_extension(path, &["raw"])
+}
+
+fn is_image_io(path: &Path) -> bool {
+    has_extension(path, &["png", "bmp", "tif", "tiff"])
+}
+
+#[cfg(feature = "image-io")]
+fn parse_image_io(contents: &[u8]) -> io::Result<GrayImage> {
+    wsq::image_io::parse(contents)
+}
+
+#[cfg(feature = "image-io")]
+fn encode_png(image: &GrayImage) -> io::Result<Vec<u8>> {
+    wsq::image_io::encode_png(image)
+}
+
+#[cfg(not(feature = "image-io"))]
+fn image_io_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "PNG, BMP and TIFF need the image-io feature")
+}
+
+#[cfg(not(feature = "image-io"))]
+fn parse_image_io(_: &[u8]) -> io::Result<GrayImage> {
+    Err(image_io_unsupported())
+}
+
+#[cfg(not(feature = "image-io"))]
+fn encode_png(_: &GrayImage) -> io::Result<Vec<u8>> {
+    Err(image_io_unsupported())
+}
+
+/// `-` stands for the standard input or output
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+fn read_input(path: &Path) -> io::Result<Vec<u8>> {
+    if is_stdio(path) {
+        let mut contents = vec![];
+        io::stdin().lock().read_to_end(&mut contents)?;
+        Ok(contents)
+    } else {
+        fs::read(path)
     }
-    Ok((args.remove(1), "".to_string()))
 }
 
-fn pgm_from_float_image(float_image: &swt::FloatImage) -> netpbm::Image<u16> {
-    fn map_float_to_u16(f: f64, min_f: f64, max_f: f64) -> u16 {
-        ((f - min_f) * u16::MAX as f64 / max_f) as u16
+fn write_output(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if is_stdio(path) {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(contents)?;
+        stdout.flush()
+    } else {
+        fs::write(path, contents)
     }
-    let data = float_image.data.iter().map(|f| map_float_to_u16(*f, float_image.min_value, float_image.max_value))
-        .collect::<Vec<_>>();
-    netpbm::Image {
-        data,
-        width: float_image.width as u32,
-        height: float_image.height as u32,
-        max_value: u16::MAX,
+}
+
+/// Image formats recognised by their first bytes
+#[derive(Debug, PartialEq)]
+enum Magic {
+    Wsq,
+    Pnm,
+    ImageIo,
+}
+
+fn detect(contents: &[u8]) -> Option<Magic> {
+    const PNG: &[u8] = b"\x89PNG";
+    let magic = [(&[0xFF, 0xA0][..], Magic::Wsq), (b"P2", Magic::Pnm), (b"P5", Magic::Pnm), (PNG, Magic::ImageIo),
+                 (b"BM", Magic::ImageIo), (b"II*\0", Magic::ImageIo), (b"MM\0*", Magic::ImageIo)];
+    magic.into_iter().find(|(prefix, _)| contents.starts_with(prefix)).map(|(_, magic)| magic)
+}
+
+/// An image to compress, or a WSQ file decompressed.
+fn read_any_image(path: &Path, options: &ImageArgs) -> io::Result<GrayImage> {
+    let contents = read_input(path)?;
+    match detect(&contents) {
+        Some(Magic::Wsq) => codec::decode(&contents),
+        _ => parse_image(path, &contents, options),
     }
 }
 
-fn f_img_from_netpbm(netpbm_image: &netpbm::Image<u16>) -> swt::FloatImage {
-    let data = netpbm_image.data.iter().map(|u| *u as f64).collect::<Vec<_>>();
-    swt::FloatImage {
-        data,
-        width: netpbm_image.width as usize,
-        height: netpbm_image.height as usize,
-        min_value: 0.0,
-        max_value: netpbm_image.max_value as f64,
+fn read_image(path: &Path, options: &ImageArgs) -> io::Result<GrayImage> {
+    parse_image(path, &read_input(path)?, options)
+}
+
+fn parse_image(path: &Path, contents: &[u8], options: &ImageArgs) -> io::Result<GrayImage> {
+    let invalid_input = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+    let mut image = match (options.width, options.height, detect(contents)) {
+        (Some(width), Some(height), _) => raw::parse(contents, width, height)?,
+        (_, _, Some(Magic::Pnm)) => GrayImage::from(&pnm::parse(contents)?),
+        (_, _, Some(Magic::ImageIo)) => parse_image_io(contents)?,
+        (_, _, Some(Magic::Wsq)) => return Err(invalid_input("already WSQ compressed")),
+        _ if is_raw(path) => return Err(invalid_input("raw input needs --width and --height")),
+        _ => return Err(invalid_input("unknown image format, raw input needs --width and --height")),
+    };
+    image.ppi = options.ppi.or(image.ppi);
+    Ok(image)
+}
+
+/// Write `image` as `format`, one of `pgm`, `raw` and `png`, or as its path's extension says.
+fn write_image(path: &Path, image: &GrayImage, format: Option<&str>) -> io::Result<()> {
+    let contents = match format {
+        Some("raw") => image.data.clone(),
+        Some("png") => encode_png(image)?,
+        Some(_) => pnm::encode(&pnm::Image::from(image), pnm::Format::Raw),
+        None if is_raw(path) => image.data.clone(),
+        None if has_extension(path, &["png"]) => encode_png(image)?,
+        None => pnm::encode(&pnm::Image::from(image), pnm::Format::Raw),
+    };
+    write_output(path, &contents)
+}
+
+/// The subbands of the decomposition of `image`, scaled to grey levels.
+fn subbands(image: &GrayImage) -> io::Result<pnm::Image> {
+    let (lowpass, highpass) = TransformTable::default().filters::<f64>();
+    let mut pixels = image.to_float_image::<f64>();
+    let (mean, rescale) = pixels.get_mean_and_rescale();
+    pixels.normalize(mean, rescale);
+    Ok(pnm::Image::from_float_image(&TwoChannelSubbandCoder::new(lowpass, highpass)?.mosaic(&pixels), 255))
+}
+
+/// Compress `image` at `bitrate`, or with `table` if there is one, returning the statistics of its
+/// subbands along with the sizes.
+fn encode_image(image: &GrayImage, output: &Path, bitrate: f64, table: Option<&QuantizationTable>)
+    -> io::Result<(batch::Conversion, Vec<codec::SubbandStatistics>)> {
+    let (codestream, statistics) = match table {
+        Some(table) => codec::encode_with_table(image, table)?,
+        None => codec::encode_with_statistics(image, bitrate)?,
+    };
+    let compressed = codestream.encode();
+    write_output(output, &compressed)?;
+    Ok((batch::Conversion { pixels: image.width * image.height, compressed_bytes: compressed.len() }, statistics))
+}
+
+fn is_encoder_input(path: &Path) -> bool {
+    has_extension(path, &["pgm", "raw"]) || cfg!(feature = "image-io") && is_image_io(path)
+}
+
+fn encode(args: &EncodeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let table = match &args.table_from {
+        Some(path) => Some(Codestream::parse(&read_input(path)?)?.quantization_table),
+        None => None,
+    };
+    if let Some(output_dir) = &args.output_dir {
+        let summary = batch::convert(&args.input, output_dir, &is_encoder_input, "wsq", args.batch.jobs,
+                                     |input, output| Ok(encode_image(&read_image(input, &args.image)?, output, args.bitrate, table.as_ref())?.0))?;
+        return report(summary);
+    }
+    let output = args.output.as_deref().expect("clap requires an output");
+    let image = read_image(&args.input, &args.image)?;
+    let (conversion, statistics) = encode_image(&image, output, args.bitrate, table.as_ref())?;
+    log::info!("{}: {} bytes, {:.3} bits per pixel", output.display(), conversion.compressed_bytes,
+               conversion.compressed_bytes as f64 * 8. / conversion.pixels as f64);
+    if let Some(path) = &args.subbands {
+        write_output(path, &pnm::encode(&subbands(&image)?, pnm::Format::Raw))?;
+    }
+    if let Some(path) = &args.statistics {
+        let mut out = vec![];
+        write_statistics(&mut out, &statistics)?;
+        write_output(path, &out)?;
     }
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    let (input_image, _) = parse_args()?;
-    let contents = fs::read(input_image)?;
-    let pgm_image = netpbm::parser::parse(&contents)?;
-    let image_copy = pgm_image.clone();
-    let mut display = netpbm::display::SdlDisplay::try_new()?;
-    display.display_netpbm(&image_copy, "Original")?;
-
-    let lowpass = Filter::WSS(vec![0.85269867900940, 0.37740285561265, -0.11062440441842, -0.02384946501938, 0.037828455506995]);
-    let highpass = Filter::WSA(vec![0.78848561640566, -0.41809227322221, -0.040689417609558, 0.064538882628938]);
-    // let lowpass = Filter::WSS(vec![0.1, 0.1, 0.1, 0.1, 0.1]);
-    // let highpass = Filter::WSA(vec![0., 0.5, 0., 0.]);
-    let subband_coder = swt::TwoChannelSubbandCoder::new(lowpass, highpass);
-
-    let mut f_img = f_img_from_netpbm(&pgm_image);
-    f_img.auto_normalize();
-    f_img.find_and_set_min_max();
-
-    let mut a = subband_coder.analysis(&f_img)?;
-    a.0.find_and_set_min_max();
-    a.1.find_and_set_min_max();
-    a.2.find_and_set_min_max();
-    a.3.find_and_set_min_max();
-    let a00_pgm = pgm_from_float_image(&a.0);
-    let a01_pgm = pgm_from_float_image(&a.1);
-    let a10_pgm = pgm_from_float_image(&a.2);
-    let a11_pgm = pgm_from_float_image(&a.3);
-    display.display_netpbm(&a00_pgm, "a00")?;
-    display.display_netpbm(&a01_pgm, "a01")?;
-    display.display_netpbm(&a10_pgm, "a10")?;
-    display.display_netpbm(&a11_pgm, "a11")?;
-    let mut reconstructed = subband_coder.synthesis(&a)?;
-    reconstructed.find_and_set_min_max();
-    let reconstructed = pgm_from_float_image(&reconstructed);
-    display.display_netpbm(&reconstructed, "Reconstruction")?;
-
-    display.wait_for_exit();
+fn decode_file(input: &Path, output: &Path, format: Option<&str>) -> io::Result<batch::Conversion> {
+    let compressed = read_input(input)?;
+    let image = codec::decode(&compressed)?;
+    let format = format.or(is_stdio(output).then_some("pgm"));
+    write_image(output, &image, format)?;
+    Ok(batch::Conversion { pixels: image.width * image.height, compressed_bytes: compressed.len() })
+}
+
+fn decode(args: &DecodeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(output_dir) = &args.output_dir {
+        let is_wsq = |path: &Path| has_extension(path, &["wsq"]);
+        let format = args.format.as_deref().unwrap_or("pgm");
+        let decode = |input: &Path, output: &Path| decode_file(input, output, Some(format));
+        return report(batch::convert(&args.input, output_dir, &is_wsq, format, args.batch.jobs, decode)?);
+    }
+    decode_file(&args.input, args.output.as_deref().expect("clap requires an output"), args.format.as_deref())?;
+    Ok(())
+}
+
+/// Print the summary of a batch, failing when any file failed.
+fn report(summary: batch::Summary) -> Result<(), Box<dyn std::error::Error>> {
+    writeln!(io::stdout(), "{}", summary)?;
+    if summary.failed > 0 {
+        return Err(format!("{} of {} files failed", summary.failed, summary.failed + summary.converted).into());
+    }
+    Ok(())
+}
+
+fn write_info(out: &mut impl Write, codestream: &Codestream) -> io::Result<()> {
+    let header = &codestream.frame_header;
+    writeln!(out, "Frame header")?;
+    writeln!(out, "  size        {}x{}", header.width, header.height)?;
+    writeln!(out, "  black       {}", header.black)?;
+    writeln!(out, "  white       {}", header.white)?;
+    writeln!(out, "  mean        {}", header.mean)?;
+    writeln!(out, "  rescale     {}", header.rescale)?;
+    writeln!(out, "  encoder     {}", header.encoder)?;
+    writeln!(out, "  software    {}", header.software)?;
+
+    let transform = &codestream.transform_table;
+    writeln!(out, "Transform table")?;
+    for (name, half) in [("lowpass", &transform.lowpass), ("highpass", &transform.highpass)] {
+        let coefficients = half.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" ");
+        writeln!(out, "  {:<11} {} taps, from the center: {}", name, 2 * half.len() - 1, coefficients)?;
+    }
+
+    let table = &codestream.quantization_table;
+    writeln!(out, "Quantization table")?;
+    writeln!(out, "  bin center  {}", table.bin_center)?;
+    writeln!(out, "  subband  bin width  zero bin width")?;
+    for k in (0..table.len()).filter(|k| table.is_quantized(*k)) {
+        writeln!(out, "  {:>7}  {:>9.6}  {:>14.6}", k, table.bin_widths[k], table.zero_bin_widths[k])?;
+    }
+    let discarded = (0..table.len()).filter(|k| !table.is_quantized(*k)).map(|k| k.to_string()).collect::<Vec<_>>();
+    writeln!(out, "  discarded   {}", discarded.join(" "))?;
+
+    writeln!(out, "Blocks")?;
+    writeln!(out, "  restart interval  {}", codestream.restart_interval)?;
+    for (i, block) in codestream.blocks.iter().enumerate() {
+        let codes = block.huffman_table.bits.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" ");
+        writeln!(out, "  block {}  Huffman table {}, {} bytes, codes per length {}", i + 1, block.huffman_table_id,
+                 block.data.iter().map(Vec::len).sum::<usize>(), codes)?;
+    }
+
+    writeln!(out, "Comments")?;
+    for comment in &codestream.comments {
+        for line in String::from_utf8_lossy(comment).lines() {
+            writeln!(out, "  {}", line)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_statistics(out: &mut impl Write, statistics: &[codec::SubbandStatistics]) -> io::Result<()> {
+    writeln!(out, "subband     size      variance     bin width  zero bin width  nonzero      bits")?;
+    for s in statistics {
+        if s.is_discarded() {
+            writeln!(out, "{:>7}  {:>7}  {:>12.4}  discarded", s.index, format!("{}x{}", s.width, s.height), s.variance)?;
+        } else {
+            writeln!(out, "{:>7}  {:>7}  {:>12.4}  {:>12.6}  {:>14.6}  {:>7}  {:>8}", s.index, format!("{}x{}", s.width, s.height),
+                     s.variance, s.bin_width, s.zero_bin_width, s.nonzero, s.bits)?;
+        }
+    }
+    let discarded = statistics.iter().filter(|s| s.is_discarded()).count();
+    writeln!(out, "{} subbands discarded, {} bits of coded coefficients", discarded, statistics.iter().map(|s| s.bits).sum::<usize>())
+}
+
+fn compare(original: &Path, other: &Path, options: &ImageArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = io::stdout().lock();
+    let comparison = metrics::compare(&read_any_image(original, options)?, &read_any_image(other, options)?)?;
+    writeln!(out, "MSE                  {:.4}", comparison.mse)?;
+    writeln!(out, "RMSE                 {:.4}", comparison.rmse)?;
+    writeln!(out, "PSNR                 {:.4} dB", comparison.psnr)?;
+    writeln!(out, "mean absolute error  {:.4}", comparison.mean_absolute_error)?;
+    writeln!(out, "max absolute error   {}", comparison.max_absolute_error)?;
+    writeln!(out, "SSIM                 {:.6}", comparison.ssim)?;
+    Ok(())
+}
+
+fn certify(directory: &Path, bitrate: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = io::stdout().lock();
+    let reports = certification::check_directory(directory, bitrate)?;
+    for report in &reports {
+        writeln!(out, "{}", report)?;
+    }
+    let failed = reports.iter().filter(|report| !report.passed()).count();
+    writeln!(out, "{} passed, {} failed", reports.len() - failed, failed)?;
+    if reports.is_empty() {
+        return Err(format!("No reference WSQ files in {}", directory.display()).into());
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} images failed", failed, reports.len()).into());
+    }
     Ok(())
 }
+
+#[cfg(feature = "viewer")]
+fn view(input: &Path, options: &ImageArgs, bitrate: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let image = read_image(input, options)?;
+    let reconstruction = codec::decode(&codec::encode(&image, bitrate)?)?;
+    let mut display = viewer::SdlDisplay::try_new()?;
+    display.display(&pnm::Image::from(&image), "Original")?;
+    display.display(&subbands(&image)?, "Subbands")?;
+    display.display(&pnm::Image::from(&reconstruction), "Reconstruction")?;
+    display.wait_for_exit()
+}
+
+fn run(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Encode(args) => encode(&args),
+        Command::Decode(args) => decode(&args),
+        Command::Requantize { input, output, bitrate } => {
+            let codestream = codec::requantize(&Codestream::parse(&read_input(&input)?)?, bitrate)?;
+            Ok(write_output(&output, &codestream.encode())?)
+        },
+        Command::Info { input } => {
+            let codestream = Codestream::parse(&read_input(&input)?)?;
+            Ok(write_info(&mut io::stdout().lock(), &codestream)?)
+        },
+        Command::Compare { original, other, image } => compare(&original, &other, &image),
+        Command::Dump { input } => Ok(wsq::codestream::dump::dump(&read_input(&input)?, &mut io::stdout().lock())?),
+        Command::Certify { directory, bitrate } => certify(&directory, bitrate),
+        #[cfg(feature = "viewer")]
+        Command::View { input, image, bitrate } => view(&input, &image, bitrate),
+    }
+}
+
+fn main() {
+    env_logger::init();
+    match run(Cli::parse().command) {
+        Ok(()) => {},
+        // the output was piped to a program that stopped reading
+        Err(e) if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe) => {},
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, Magic};
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Some(Magic::Wsq), detect(b"\xFF\xA0\xFF\xA8"));
+        assert_eq!(Some(Magic::Pnm), detect(b"P2\n1 1\n255\n0\n"));
+        assert_eq!(Some(Magic::Pnm), detect(b"P5\n1 1\n255\n\0"));
+        assert_eq!(Some(Magic::ImageIo), detect(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(Some(Magic::ImageIo), detect(b"BM\x3a\0\0\0"));
+        assert_eq!(Some(Magic::ImageIo), detect(b"II*\0\x08\0\0\0"));
+        assert_eq!(Some(Magic::ImageIo), detect(b"MM\0*\0\0\0\x08"));
+        assert_eq!(None, detect(b"P6\n1 1\n255\n\0\0\0"));
+        assert_eq!(None, detect(b"MM*\0"));
+        assert_eq!(None, detect(b"\xFF"));
+        assert_eq!(None, detect(b""));
+    }
+}
@@ -0,0 +1,150 @@
+//! Module for measuring how far a decoded image is from its original
+//
+// SSIM follows Wang et al., "Image quality assessment: from error visibility to structural
+// similarity", 2004: the local statistics are weighted by an 11x11 Gaussian window of standard
+// deviation 1.5 placed wherever it fits in the image, with the constants (0.01 L)² and (0.03 L)²
+// for L = 255, and the index is their mean. Images smaller than the window are taken as one window.
+use std::io;
+
+use crate::gray::GrayImage;
+
+const MAX_VALUE: f64 = 255.;
+const WINDOW_SIZE: usize = 11;
+const WINDOW_SIGMA: f64 = 1.5;
+const C1: f64 = (0.01 * MAX_VALUE) * (0.01 * MAX_VALUE);
+const C2: f64 = (0.03 * MAX_VALUE) * (0.03 * MAX_VALUE);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub mse: f64,
+    pub rmse: f64,
+    /// Peak signal-to-noise ratio in dB, infinite for identical images
+    pub psnr: f64,
+    pub mean_absolute_error: f64,
+    pub max_absolute_error: u8,
+    pub ssim: f64,
+}
+
+fn check_sizes(a: &GrayImage, b: &GrayImage) -> io::Result<()> {
+    if (a.width, a.height) != (b.width, b.height) || a.data.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("Cannot compare a {}x{} image with a {}x{} one", a.width, a.height, b.width, b.height)));
+    }
+    Ok(())
+}
+
+pub fn mse(a: &GrayImage, b: &GrayImage) -> io::Result<f64> {
+    check_sizes(a, b)?;
+    let sum = a.data.iter().zip(&b.data).map(|(a, b)| (*a as f64 - *b as f64).powi(2)).sum::<f64>();
+    Ok(sum / a.data.len() as f64)
+}
+
+pub fn psnr(a: &GrayImage, b: &GrayImage) -> io::Result<f64> {
+    Ok(10. * (MAX_VALUE * MAX_VALUE / mse(a, b)?).log10())
+}
+
+pub fn mean_absolute_error(a: &GrayImage, b: &GrayImage) -> io::Result<f64> {
+    check_sizes(a, b)?;
+    Ok(a.data.iter().zip(&b.data).map(|(a, b)| a.abs_diff(*b) as f64).sum::<f64>() / a.data.len() as f64)
+}
+
+pub fn max_absolute_error(a: &GrayImage, b: &GrayImage) -> io::Result<u8> {
+    check_sizes(a, b)?;
+    Ok(a.data.iter().zip(&b.data).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0))
+}
+
+/// Weighted sums of `values` over every position of the separable `window` that fits in the image.
+fn filter(values: &[f64], width: usize, height: usize, window: &[f64]) -> Vec<f64> {
+    let (out_width, out_height) = (width + 1 - window.len(), height + 1 - window.len());
+    let mut rows = vec![0.; out_width * height];
+    for y in 0..height {
+        for x in 0..out_width {
+            rows[y * out_width + x] = window.iter().enumerate().map(|(i, w)| w * values[y * width + x + i]).sum();
+        }
+    }
+    let mut out = vec![0.; out_width * out_height];
+    for y in 0..out_height {
+        for x in 0..out_width {
+            out[y * out_width + x] = window.iter().enumerate().map(|(i, w)| w * rows[(y + i) * out_width + x]).sum();
+        }
+    }
+    out
+}
+
+pub fn ssim(a: &GrayImage, b: &GrayImage) -> io::Result<f64> {
+    check_sizes(a, b)?;
+    let (width, height) = (a.width, a.height);
+    let window = if width >= WINDOW_SIZE && height >= WINDOW_SIZE {
+        let weights = (0..WINDOW_SIZE)
+            .map(|i| (-((i as f64 - (WINDOW_SIZE / 2) as f64).powi(2)) / (2. * WINDOW_SIGMA * WINDOW_SIGMA)).exp())
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f64>();
+        weights.iter().map(|w| w / total).collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+    let x = a.data.iter().map(|v| *v as f64).collect::<Vec<_>>();
+    let y = b.data.iter().map(|v| *v as f64).collect::<Vec<_>>();
+    let products = |p: &[f64], q: &[f64]| p.iter().zip(q).map(|(p, q)| p * q).collect::<Vec<_>>();
+    let statistics = [x.clone(), y.clone(), products(&x, &x), products(&y, &y), products(&x, &y)].map(|values| {
+        if window.is_empty() {
+            vec![values.iter().sum::<f64>() / values.len() as f64]
+        } else {
+            filter(&values, width, height, &window)
+        }
+    });
+    let [mean_x, mean_y, xx, yy, xy] = &statistics;
+    let indices = (0..mean_x.len()).map(|i| {
+        let (mx, my) = (mean_x[i], mean_y[i]);
+        let (var_x, var_y, covariance) = (xx[i] - mx * mx, yy[i] - my * my, xy[i] - mx * my);
+        ((2. * mx * my + C1) * (2. * covariance + C2)) / ((mx * mx + my * my + C1) * (var_x + var_y + C2))
+    });
+    Ok(indices.sum::<f64>() / mean_x.len() as f64)
+}
+
+pub fn compare(a: &GrayImage, b: &GrayImage) -> io::Result<Comparison> {
+    let mse = mse(a, b)?;
+    Ok(Comparison {
+        mse,
+        rmse: mse.sqrt(),
+        psnr: psnr(a, b)?,
+        mean_absolute_error: mean_absolute_error(a, b)?,
+        max_absolute_error: max_absolute_error(a, b)?,
+        ssim: ssim(a, b)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, ssim};
+    use crate::codec::tests::fingerprint;
+    use crate::codec::{decode, encode};
+    use crate::gray::GrayImage;
+
+    #[test]
+    fn test_identical_and_offset() {
+        let image = fingerprint(40, 30);
+        let identical = compare(&image, &image).unwrap();
+        assert_eq!((0., f64::INFINITY, 0), (identical.mse, identical.psnr, identical.max_absolute_error));
+        assert!((identical.ssim - 1.).abs() < 1e-12);
+
+        let flat = GrayImage::new(20, 20, vec![100; 400]).unwrap();
+        let brighter = GrayImage::new(20, 20, vec![102; 400]).unwrap();
+        let offset = compare(&flat, &brighter).unwrap();
+        assert_eq!((4., 2., 2., 2), (offset.mse, offset.rmse, offset.mean_absolute_error, offset.max_absolute_error));
+        assert!((offset.psnr - 42.1102).abs() < 1e-4, "{}", offset.psnr);
+        assert!(offset.ssim < 1. && offset.ssim > 0.99);
+
+        assert!(compare(&flat, &image).is_err());
+        let tiny = GrayImage::new(2, 1, vec![0, 255]).unwrap();
+        assert!((ssim(&tiny, &tiny).unwrap() - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_bitrates() {
+        let image = fingerprint(200, 200);
+        let [coarse, fine] = [0.3, 2.].map(|bitrate| compare(&image, &decode(&encode(&image, bitrate).unwrap()).unwrap()).unwrap());
+        assert!(fine.psnr > coarse.psnr && fine.ssim > coarse.ssim && fine.mean_absolute_error < coarse.mean_absolute_error);
+        assert!(coarse.ssim > 0.3 && fine.ssim < 1.);
+    }
+}
@@ -0,0 +1,229 @@
+//! Module for reading and writing grey scale netpbm images, PGM in its plain (P2) and raw (P5) form
+//
+// The header is the magic number, the width, the height and the maximum sample value, separated by
+// whitespace, where a '#' starts a comment that runs to the end of the line. In a raw file a single
+// whitespace character separates the header from the samples, which are one byte when the maximum
+// value is below 256 and two bytes, most significant first, otherwise. In a plain file the samples
+// are written as decimal numbers separated by whitespace.
+use std::io;
+use std::io::{Read, Write};
+
+use crate::swt::{FloatImage, Sample};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// P2, samples as decimal text
+    Plain,
+    /// P5, samples as binary
+    Raw,
+}
+
+impl Format {
+    fn magic(&self) -> &'static [u8] {
+        match self {
+            Format::Plain => b"P2",
+            Format::Raw => b"P5",
+        }
+    }
+}
+
+/// A grey scale image with samples from 0 up to and including `max_value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub data: Vec<u16>,
+    pub width: usize,
+    pub height: usize,
+    pub max_value: u16,
+}
+
+impl Image {
+    /// Map the range `min_value` to `max_value` of `float_image` onto 0 to `max_value`.
+    pub fn from_float_image<F: Sample>(float_image: &FloatImage<F>, max_value: u16) -> Self {
+        let min_f = float_image.min_value.to_f64();
+        let range = float_image.max_value.to_f64() - min_f;
+        let scale = if range > 0. { max_value as f64 / range } else { 0. };
+        let data = float_image.data.iter()
+            .map(|f| ((f.to_f64() - min_f) * scale).round().clamp(0., max_value as f64) as u16)
+            .collect();
+        Image { data, width: float_image.width, height: float_image.height, max_value }
+    }
+
+    pub fn to_float_image<F: Sample>(&self) -> FloatImage<F> {
+        FloatImage {
+            data: self.data.iter().map(|u| F::from_i32(*u as i32)).collect(),
+            width: self.width,
+            height: self.height,
+            min_value: F::from_i32(0),
+            max_value: F::from_i32(self.max_value as i32),
+        }
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        if self.max_value < 256 { 1 } else { 2 }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Parser<'a> {
+    contents: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(c) = self.contents.get(self.position) {
+            if *c == b'#' {
+                while self.contents.get(self.position).is_some_and(|c| *c != b'\n' && *c != b'\r') {
+                    self.position += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn number(&mut self, name: &str) -> io::Result<usize> {
+        self.skip_whitespace_and_comments();
+        let start = self.position;
+        while self.contents.get(self.position).is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.contents[start..self.position]).ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| invalid_data(format!("Expected {} at byte {}", name, start)))
+    }
+
+    fn sample(&mut self, max_value: u16) -> io::Result<u16> {
+        let sample = self.number("sample")?;
+        if sample > max_value as usize {
+            return Err(invalid_data(format!("Sample {} exceeds the maximum value {}", sample, max_value)));
+        }
+        Ok(sample as u16)
+    }
+}
+
+pub fn parse(contents: &[u8]) -> io::Result<Image> {
+    let format = match contents.get(..2) {
+        Some(b"P2") => Format::Plain,
+        Some(b"P5") => Format::Raw,
+        _ => return Err(invalid_data("Not a grey scale netpbm image, expected P2 or P5".to_string())),
+    };
+    let mut parser = Parser { contents, position: 2 };
+    let width = parser.number("width")?;
+    let height = parser.number("height")?;
+    let max_value = parser.number("maximum value")?;
+    if max_value == 0 || max_value > u16::MAX as usize {
+        return Err(invalid_data(format!("Maximum value {} out of range", max_value)));
+    }
+    let samples = width.checked_mul(height)
+        .ok_or_else(|| invalid_data(format!("Image dimensions {}x{} too large", width, height)))?;
+    // the header is not trusted with allocations, plain samples are collected as they are read
+    let mut image = Image { data: vec![], width, height, max_value: max_value as u16 };
+    match format {
+        Format::Plain => {
+            for _ in 0..samples {
+                image.data.push(parser.sample(image.max_value)?);
+            }
+        }
+        Format::Raw => {
+            // exactly one whitespace character ends the header, the samples are checked to be
+            // present before anything is allocated for them
+            if !contents.get(parser.position).is_some_and(u8::is_ascii_whitespace) {
+                return Err(invalid_data("Expected whitespace after the maximum value".to_string()));
+            }
+            let start = parser.position + 1;
+            let available = contents.len().saturating_sub(start);
+            let len = samples.checked_mul(image.bytes_per_sample()).filter(|len| *len <= available)
+                .ok_or_else(|| invalid_data(format!("Expected {} samples of {} bytes, found {} bytes", samples, image.bytes_per_sample(), available)))?;
+            let bytes = &contents[start..start + len];
+            if image.bytes_per_sample() == 1 {
+                image.data.extend(bytes.iter().map(|b| *b as u16));
+            } else {
+                image.data.extend(bytes.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]])));
+            }
+            if let Some(sample) = image.data.iter().find(|s| **s > image.max_value) {
+                return Err(invalid_data(format!("Sample {} exceeds the maximum value {}", sample, image.max_value)));
+            }
+        }
+    }
+    Ok(image)
+}
+
+pub fn read<R: Read>(mut reader: R) -> io::Result<Image> {
+    let mut contents = vec![];
+    reader.read_to_end(&mut contents)?;
+    parse(&contents)
+}
+
+pub fn encode(image: &Image, format: Format) -> Vec<u8> {
+    let mut contents = vec![];
+    contents.extend_from_slice(format.magic());
+    contents.extend_from_slice(format!("\n{} {}\n{}\n", image.width, image.height, image.max_value).as_bytes());
+    match format {
+        Format::Plain => {
+            for row in image.data.chunks(image.width.max(1)) {
+                let row = row.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(" ");
+                contents.extend_from_slice(row.as_bytes());
+                contents.push(b'\n');
+            }
+        }
+        Format::Raw if image.bytes_per_sample() == 1 => contents.extend(image.data.iter().map(|s| *s as u8)),
+        Format::Raw => contents.extend(image.data.iter().flat_map(|s| s.to_be_bytes())),
+    }
+    contents
+}
+
+pub fn write<W: Write>(image: &Image, format: Format, mut writer: W) -> io::Result<()> {
+    writer.write_all(&encode(image, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, parse, Format, Image};
+
+    #[test]
+    fn test_parse_plain() {
+        let image = parse(b"P2\n# a comment\n3 2 # another\n15\n0 1 2\n13 14 15\n").unwrap();
+        assert_eq!(Image { data: vec![0, 1, 2, 13, 14, 15], width: 3, height: 2, max_value: 15 }, image);
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        let image = parse(b"P5 2 2 255\n\x00\x0a\xff\x0a").unwrap();
+        assert_eq!(vec![0, 10, 255, 10], image.data);
+        let image = parse(b"P5\n2 1\n65535\n\x01\x00\xff\xff").unwrap();
+        assert_eq!(vec![256, 65535], image.data);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(b"P6\n1 1\n255\n\x00\x00\x00").is_err());
+        assert!(parse(b"P5\n2 2\n255\n\x00\x00\x00").is_err());
+        assert!(parse(b"P2\n2 1\n10\n5 11\n").is_err());
+        assert!(parse(b"P5\n2 1\n1000\n\x03\xe8\x03\xe9").is_err());
+        assert!(parse(b"P5 0 0 255").is_err());
+    }
+
+    #[test]
+    fn test_parse_oversized_header() {
+        assert!(parse(b"P2 100000 100000 255\n1").is_err());
+        assert!(parse(b"P5 100000 100000 255\n\x01").is_err());
+        assert!(parse(format!("P2 {} {} 255\n1", usize::MAX, 2).as_bytes()).is_err());
+        assert!(parse(format!("P5 {} 1 65535\n\x01\x02", usize::MAX / 2 + 1).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for max_value in [255, 4095] {
+            let image = Image { data: vec![0, 17, max_value, 1, 2, 3], width: 2, height: 3, max_value };
+            for format in [Format::Plain, Format::Raw] {
+                assert_eq!(image, parse(&encode(&image, format)).unwrap());
+            }
+        }
+    }
+}
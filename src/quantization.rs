@@ -1,7 +1,278 @@
 //! Module for (de)quantization in the WSQ
-pub mod quantizer {}
+//
+// The subband coefficients are quantized with a uniform scalar quantizer with a widened zero bin:
+//          ⎧ ⌊(a - Z/2) / Q⌋ + 1,  a > Z/2
+//     p =  ⎨ 0,                    -Z/2 ≤ a ≤ Z/2
+//          ⎩ ⌈(a + Z/2) / Q⌉ - 1,  a < -Z/2
+// and reconstructed towards the center of the bin with the bin center parameter C:
+//          ⎧ (p - C) Q + Z/2,  p > 0
+//     â =  ⎨ 0,                p = 0
+//          ⎩ (p + C) Q - Z/2,  p < 0
+// A bin width Q of zero means the subband is not transmitted and all its coefficients are zero.
+//
+// Encoder number two derives the bin widths from the subband variances σ², Part 3 section 3. With
+// relative bin widths Q'ₖ = 1 for the four lowest subbands and 10 / (Aₖ ln σₖ²) for the others,
+// the downsampling factors mₖ and S = Σ 1/mₖ over the subbands K still in the allocation,
+//     q = 2^(r/S - 1) / 2.5 / Π (σₖ / Q'ₖ)^(1/(mₖ S))
+// meets the bitrate r. Subbands with Q'ₖ / q ≥ 5 σₖ would get a negative bitrate, they leave K
+// and q is computed again. Finally Qₖ = Q'ₖ / q and Zₖ = 1.2 Qₖ.
+use crate::swt::{FloatImage, Sample};
+use crate::swt::layout::{Subband, SubbandLayout, NUM_QUANTIZED_SUBBANDS, NUM_SUBBANDS};
 
-pub mod dequantizer {}
+/// Bin center C of encoder number two
+pub const BIN_CENTER: f64 = 0.44;
+/// Subbands with a smaller variance are discarded
+pub const VARIANCE_THRESHOLD: f64 = 1.01;
+/// If the variances of the four lowest subbands over the central regions add up to less, the
+/// variances are taken over the whole subbands
+pub const CROPPED_VARIANCE_THRESHOLD: f64 = 20000.;
+const ZERO_BIN_RATIO: f64 = 1.2;
+/// Loading factor γ, the coefficients are assumed to lie within ±γσ
+const LOADING_FACTOR: f64 = 2.5;
 
-#[derive(Debug)]
-pub struct QuantizationTable {}
\ No newline at end of file
+/// Bin widths and zero bin widths per subband, with the bin center used for reconstruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizationTable {
+    pub bin_center: f64,
+    pub bin_widths: Vec<f64>,
+    pub zero_bin_widths: Vec<f64>,
+}
+
+impl QuantizationTable {
+    pub fn new(bin_center: f64, bin_widths: Vec<f64>, zero_bin_widths: Vec<f64>) -> Self {
+        Self { bin_center, bin_widths, zero_bin_widths }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bin_widths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bin_widths.is_empty()
+    }
+
+    /// Whether the coefficients of `subband` are transmitted at all.
+    pub fn is_quantized(&self, subband: usize) -> bool {
+        self.bin_widths.get(subband).is_some_and(|q| *q != 0.)
+    }
+
+    /// Bin widths of encoder number two for the subband `variances` and a `bitrate` in bits per
+    /// pixel, for the subbands of `layout`.
+    pub fn for_bitrate(variances: &[f64], layout: &SubbandLayout, bitrate: f64) -> Self {
+        let weight = |k: usize| match k {
+            52 | 56 => 1.32,
+            53 | 58 | 55 | 59 => 1.08,
+            54 | 57 => 1.42,
+            _ => 1.,
+        };
+        let relative = (0..NUM_QUANTIZED_SUBBANDS)
+            .map(|k| if k < 4 { 1. } else { 10. / (weight(k) * variances[k].ln()) })
+            .collect::<Vec<_>>();
+        let sigma = variances.iter().map(|v| v.sqrt()).collect::<Vec<_>>();
+        let fraction = |k: usize| 0.25f64.powi(layout.get(k).map_or(0, |s| s.level()) as i32);
+
+        let initial = (0..NUM_QUANTIZED_SUBBANDS).filter(|k| variances[*k] >= VARIANCE_THRESHOLD).collect::<Vec<_>>();
+        let mut allocated = initial.clone();
+        let mut q = 1.;
+        while !allocated.is_empty() {
+            let s = allocated.iter().map(|k| fraction(*k)).sum::<f64>();
+            let product = allocated.iter().map(|k| (sigma[*k] / relative[*k]).powf(fraction(*k))).product::<f64>();
+            q = 2f64.powf(bitrate / s - 1.) / LOADING_FACTOR / product.powf(1. / s);
+            let before = allocated.len();
+            allocated.retain(|k| relative[*k] / q < 2. * LOADING_FACTOR * sigma[*k]);
+            if allocated.len() == before {
+                break;
+            }
+        }
+
+        let mut bin_widths = vec![0.; NUM_SUBBANDS];
+        if !allocated.is_empty() {
+            for k in initial {
+                bin_widths[k] = relative[k] / q;
+            }
+        }
+        let zero_bin_widths = bin_widths.iter().map(|q| q * ZERO_BIN_RATIO).collect();
+        Self::new(BIN_CENTER, bin_widths, zero_bin_widths)
+    }
+}
+
+/// The central region of `subband` its variance is estimated over: from an eighth of the width
+/// and 9/32 of the height on, 3/4 of the width wide and 7/16 of the height high, Part 3 section 3.1.
+pub fn central_region(subband: &Subband) -> Subband {
+    Subband {
+        x: subband.x + subband.width / 8,
+        y: subband.y + 9 * subband.height / 32,
+        width: 3 * subband.width / 4,
+        height: 7 * subband.height / 16,
+        ..subband.clone()
+    }
+}
+
+/// Unbiased variance of the coefficients of `region` in the decomposed `image`, 0 for fewer than
+/// two coefficients.
+pub fn variance<F: Sample>(image: &FloatImage<F>, region: &Subband) -> f64 {
+    let n = region.len() as f64;
+    if n < 2. {
+        return 0.;
+    }
+    let (mut sum, mut sum_of_squares) = (0., 0.);
+    for row in region.y..region.y + region.height {
+        for a in &image.data[row * image.width + region.x..row * image.width + region.x + region.width] {
+            let a = a.to_f64();
+            sum += a;
+            sum_of_squares += a * a;
+        }
+    }
+    (sum_of_squares - sum * sum / n) / (n - 1.)
+}
+
+/// Variances of the quantized subbands of the decomposed `image`, over the central region of every
+/// subband unless the variances of the four lowest subbands over theirs add up to less than
+/// `CROPPED_VARIANCE_THRESHOLD`, then over the whole subbands, Part 3 section 3.1.
+pub fn subband_variances<F: Sample>(image: &FloatImage<F>, layout: &SubbandLayout) -> Vec<f64> {
+    let quantized = || layout.quantized();
+    if quantized().take(4).map(|s| variance(image, &central_region(s))).sum::<f64>() < CROPPED_VARIANCE_THRESHOLD {
+        quantized().map(|s| variance(image, s)).collect()
+    } else {
+        quantized().map(|s| variance(image, &central_region(s))).collect()
+    }
+}
+
+pub mod quantizer {
+    use crate::swt::Sample;
+
+    pub fn quantize<F: Sample>(coefficient: F, bin_width: f64, zero_bin_width: f64) -> i32 {
+        let half_zero_bin = F::from_f64(zero_bin_width / 2.);
+        let bin_width = F::from_f64(bin_width);
+        if coefficient > half_zero_bin {
+            ((coefficient - half_zero_bin) / bin_width).to_f64() as i32 + 1
+        } else if coefficient < -half_zero_bin {
+            ((coefficient + half_zero_bin) / bin_width).to_f64() as i32 - 1
+        } else {
+            0
+        }
+    }
+
+    pub fn quantize_subband<F: Sample>(coefficients: &[F], bin_width: f64, zero_bin_width: f64) -> Vec<i32> {
+        if bin_width == 0. {
+            return vec![0; coefficients.len()];
+        }
+        coefficients.iter().map(|a| quantize(*a, bin_width, zero_bin_width)).collect()
+    }
+}
+
+pub mod dequantizer {
+    use crate::swt::Sample;
+
+    pub fn dequantize<F: Sample>(index: i32, bin_width: f64, zero_bin_width: f64, bin_center: f64) -> F {
+        Bin::new(bin_width, zero_bin_width, bin_center).dequantize(index)
+    }
+
+    /// Dequantizes a subband, converting its table entries to `F` once and not per coefficient.
+    pub fn dequantize_subband<F: Sample>(indices: &[i32], bin_width: f64, zero_bin_width: f64, bin_center: f64) -> Vec<F> {
+        let bin = Bin::new(bin_width, zero_bin_width, bin_center);
+        indices.iter().map(|p| bin.dequantize(*p)).collect()
+    }
+
+    struct Bin<F> {
+        width: F,
+        half_zero_width: F,
+        center: F,
+    }
+
+    impl<F: Sample> Bin<F> {
+        fn new(bin_width: f64, zero_bin_width: f64, bin_center: f64) -> Self {
+            Bin { width: F::from_f64(bin_width), half_zero_width: F::from_f64(zero_bin_width / 2.), center: F::from_f64(bin_center) }
+        }
+
+        fn dequantize(&self, index: i32) -> F {
+            match index {
+                0 => F::default(),
+                p if p > 0 => self.width * (F::from_i32(p) - self.center) + self.half_zero_width,
+                p => self.width * (F::from_i32(p) + self.center) - self.half_zero_width,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dequantizer::dequantize;
+    use super::quantizer::quantize;
+    use super::{central_region, subband_variances, variance, QuantizationTable};
+    use crate::swt::layout::SubbandLayout;
+    use crate::swt::FloatImage;
+
+    #[test]
+    fn test_quantize_zero_bin() {
+        assert_eq!(0, quantize(1.1f64, 2., 2.4));
+        assert_eq!(0, quantize(-1.2f64, 2., 2.4));
+        assert_eq!(1, quantize(1.3f64, 2., 2.4));
+        assert_eq!(-1, quantize(-1.3f64, 2., 2.4));
+        assert_eq!(2, quantize(3.3f64, 2., 2.4));
+        assert_eq!(-2, quantize(-3.3f32, 2., 2.4));
+    }
+
+    #[test]
+    fn test_dequantize_into_bin() {
+        for p in [-3, -1, 1, 2, 5] {
+            let a: f64 = dequantize(p, 2., 2.4, 0.44);
+            assert_eq!(p, quantize(a, 2., 2.4));
+            let a: f32 = dequantize(p, 2., 2.4, 0.44);
+            assert_eq!(p, quantize(a, 2., 2.4));
+        }
+        assert_eq!(0., dequantize::<f64>(0, 2., 2.4, 0.44));
+    }
+
+    #[test]
+    fn test_bit_allocation() {
+        let layout = SubbandLayout::new(500, 500);
+        let variances = (0..60).map(|k| 2000. / (k as f64 + 1.)).collect::<Vec<_>>();
+        let low = QuantizationTable::for_bitrate(&variances, &layout, 0.75);
+        let high = QuantizationTable::for_bitrate(&variances, &layout, 2.25);
+        assert_eq!(64, low.len());
+        assert!((0..60).all(|k| low.is_quantized(k) && low.bin_widths[k] > high.bin_widths[k]));
+        assert!((60..64).all(|k| !low.is_quantized(k)));
+        assert!((0..60).all(|k| (low.zero_bin_widths[k] - 1.2 * low.bin_widths[k]).abs() < 1e-12));
+        // the four lowest subbands share a bin width
+        assert!((1..4).all(|k| low.bin_widths[k] == low.bin_widths[0]));
+
+        let mut flat = variances.clone();
+        flat[30] = 1.;
+        assert!(!QuantizationTable::for_bitrate(&flat, &layout, 0.75).is_quantized(30));
+    }
+
+    #[test]
+    fn test_subband_variances() {
+        let layout = SubbandLayout::new(256, 200);
+        let s0 = layout.subbands()[0].clone();
+        let region = central_region(&s0);
+        assert_eq!((s0.x + s0.width / 8, s0.y + 9 * s0.height / 32), (region.x, region.y));
+        assert_eq!((3 * s0.width / 4, 7 * s0.height / 16), (region.width, region.height));
+
+        // one large coefficient in the corner of subband 0, outside its central region
+        let mut rows = vec![vec![0f64; 256]; 200];
+        rows[s0.y][s0.x] = 1000.;
+        let variances = subband_variances(&FloatImage::from(rows.clone()), &layout);
+        assert_eq!(60, variances.len());
+        assert!((variances[0] - 1000. * 1000. / s0.len() as f64).abs() < 1e-6);
+        assert!(variances[1..].iter().all(|v| *v == 0.));
+
+        // enough variation in the central regions of the four lowest subbands to use them
+        for s in &layout.subbands()[..4] {
+            let region = central_region(s);
+            for (y, row) in rows.iter_mut().enumerate().skip(region.y).take(region.height) {
+                for (x, a) in row.iter_mut().enumerate().skip(region.x).take(region.width) {
+                    *a = if (x + y) % 2 == 0 { 200. } else { -200. };
+                }
+            }
+        }
+        let image = FloatImage::from(rows.clone());
+        let variances = subband_variances(&image, &layout);
+        assert!((0..4).all(|k| variances[k] == variance(&image, &central_region(&layout.subbands()[k]))));
+        assert_ne!(variances[0], variance(&image, &s0));
+        let rows = rows.iter().map(|r| r.iter().map(|a| *a as f32).collect()).collect::<Vec<Vec<f32>>>();
+        let single = subband_variances(&FloatImage::from(rows), &layout);
+        assert!(variances.iter().zip(&single).all(|(a, b)| (a - b).abs() < 1e-6 * a.max(1.)));
+    }
+}
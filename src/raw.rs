@@ -0,0 +1,51 @@
+//! Module for headerless 8-bit grey scale buffers, one byte per pixel row after row
+//
+// Nothing in the file says how large the image is, so the width and height, and the scan
+// resolution when it matters, have to come from the caller, as with the `.raw` files of NBIS.
+use std::io;
+use std::io::{Read, Write};
+
+use crate::gray::GrayImage;
+
+pub fn parse(contents: &[u8], width: usize, height: usize) -> io::Result<GrayImage> {
+    let len = width.checked_mul(height)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Image dimensions {}x{} too large", width, height)))?;
+    if contents.len() != len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("Expected {} bytes for a {}x{} raw image, found {}", len, width, height, contents.len())));
+    }
+    GrayImage::new(width, height, contents.to_vec())
+}
+
+pub fn read<R: Read>(mut reader: R, width: usize, height: usize) -> io::Result<GrayImage> {
+    let mut contents = vec![];
+    reader.read_to_end(&mut contents)?;
+    parse(&contents, width, height)
+}
+
+pub fn write<W: Write>(image: &GrayImage, mut writer: W) -> io::Result<()> {
+    writer.write_all(&image.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{parse, read, write};
+
+    #[test]
+    fn test_round_trip() {
+        let image = read(&[0u8, 1, 2, 253, 254, 255][..], 3, 2).unwrap();
+        assert_eq!((3, 2, None), (image.width, image.height, image.ppi));
+        let mut contents = vec![];
+        write(&image, &mut contents).unwrap();
+        assert_eq!(vec![0, 1, 2, 253, 254, 255], contents);
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        assert!(parse(&[0; 5], 3, 2).is_err());
+        assert!(parse(&[0; 7], 3, 2).is_err());
+        assert_eq!(io::ErrorKind::InvalidInput, parse(&[0; 2], usize::MAX, 2).unwrap_err().kind());
+    }
+}
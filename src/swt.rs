@@ -1,29 +1,90 @@
 use std::error::Error;
-use std::slice;
+use std::io;
 
 pub mod filter;
+pub mod fixed;
+pub mod layout;
 pub mod signal;
+pub mod tree;
+
+/// Numeric type the transform and quantization can be computed in.
+///
+/// Implemented for `f64`, `f32` and `fixed::Fixed`, so callers can trade precision for halving the memory of every
+/// image buffer by picking the sample type of the filters they construct the coder with.
+pub trait Sample: Copy + Default + PartialOrd + std::fmt::Debug + std::iter::Sum + std::ops::AddAssign
++ std::ops::Add<Output=Self> + std::ops::Sub<Output=Self> + std::ops::Mul<Output=Self>
++ std::ops::Div<Output=Self> + std::ops::Neg<Output=Self> {
+    const MIN: Self;
+    const MAX: Self;
+
+    fn from_f64(value: f64) -> Self;
+    fn from_i32(value: i32) -> Self;
+    fn to_f64(self) -> f64;
+    /// Nearest integer with halves rounded up, how the decoder turns samples into pixels.
+    fn round_half_up(self) -> i32;
+}
+
+impl Sample for f64 {
+    const MIN: Self = f64::MIN;
+    const MAX: Self = f64::MAX;
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn from_i32(value: i32) -> Self {
+        value as f64
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn round_half_up(self) -> i32 {
+        (self + 0.5).floor() as i32
+    }
+}
+
+impl Sample for f32 {
+    const MIN: Self = f32::MIN;
+    const MAX: Self = f32::MAX;
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn from_i32(value: i32) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
 
+    fn round_half_up(self) -> i32 {
+        (self + 0.5).floor() as i32
+    }
+}
 
-pub struct FloatImage {
-    pub data: Vec<f64>,
+pub struct FloatImage<F = f64> {
+    pub data: Vec<F>,
     pub width: usize,
     pub height: usize,
-    pub min_value: f64,
-    pub max_value: f64,
+    pub min_value: F,
+    pub max_value: F,
 }
 
-impl From<Vec<Vec<f64>>> for FloatImage {
-    fn from(value: Vec<Vec<f64>>) -> Self {
+impl<F: Sample> From<Vec<Vec<F>>> for FloatImage<F> {
+    fn from(value: Vec<Vec<F>>) -> Self {
         let width = value[0].len();
         let height = value.len();
-        let data = value.into_iter().flatten().collect::<Vec<f64>>();
+        let data = value.into_iter().flatten().collect::<Vec<F>>();
         FloatImage {
             data,
             width,
             height,
-            min_value: 0.,
-            max_value: 1.,
+            min_value: F::from_f64(0.),
+            max_value: F::from_f64(1.),
         }
     }
 }
@@ -60,16 +121,56 @@ impl<T> Iterator for Columns<'_, T> where T: Copy {
     }
 }
 
-impl FloatImage {
+impl<F: Sample> FloatImage<F> {
+    pub fn zeros(width: usize, height: usize) -> Self {
+        FloatImage {
+            data: vec![F::default(); width * height],
+            width,
+            height,
+            min_value: F::from_f64(0.),
+            max_value: F::from_f64(1.),
+        }
+    }
+
+    /// Copy of the `width` by `height` rectangle with its top left corner at `x`, `y`.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Self {
+        let mut data = Vec::with_capacity(width * height);
+        for row in y..y + height {
+            let start = row * self.width + x;
+            data.extend_from_slice(&self.data[start..start + width]);
+        }
+        FloatImage { data, width, height, min_value: self.min_value, max_value: self.max_value }
+    }
+
+    /// Copy `image` into this image with its top left corner at `x`, `y`.
+    pub fn paste(&mut self, x: usize, y: usize, image: &FloatImage<F>) {
+        for (row, samples) in image.data.chunks(image.width.max(1)).enumerate() {
+            let start = (y + row) * self.width + x;
+            self.data[start..start + image.width].copy_from_slice(samples);
+        }
+    }
+
+    /// Copy of this image with every sample converted to another sample type.
+    pub fn convert<G: Sample>(&self) -> FloatImage<G> {
+        FloatImage {
+            data: self.data.iter().map(|f| G::from_f64(f.to_f64())).collect(),
+            width: self.width,
+            height: self.height,
+            min_value: G::from_f64(self.min_value.to_f64()),
+            max_value: G::from_f64(self.max_value.to_f64()),
+        }
+    }
+
     pub fn rotate(&mut self) {
         self.data = self.columns().flatten().collect();
-        let tmp = self.width;
-        self.width = self.height;
-        self.height = tmp;
+        std::mem::swap(&mut self.width, &mut self.height);
     }
+
+    /// The mean is accumulated in `f64` whatever the sample type, a sum over a whole image in `f32`
+    /// would lose the low order bits of the mean.
     pub fn get_mean_and_rescale(&self) -> (f64, f64) {
-        let mean = self.data.iter().sum::<f64>() / self.data.len() as f64;
-        let rescale = f64::max(self.max_value - mean, mean - self.min_value) / 128.;
+        let mean = self.data.iter().map(|f| f.to_f64()).sum::<f64>() / self.data.len() as f64;
+        let rescale = f64::max(self.max_value.to_f64() - mean, mean - self.min_value.to_f64()) / 128.;
         (mean, rescale)
     }
 
@@ -80,178 +181,493 @@ impl FloatImage {
     }
 
     pub fn normalize(&mut self, mean: f64, rescale: f64) {
+        let mean = F::from_f64(mean);
+        let rescale = F::from_f64(rescale);
         for f in self.data.iter_mut() {
             *f = (*f - mean) / rescale;
         }
     }
 
-    fn columns(&self) -> Columns<'_, f64> {
-        Columns::new(&self.data, self.width)
+    /// Inverse of `normalize`, the last step of decoding before the pixels are rounded.
+    pub fn denormalize(&mut self, mean: f64, rescale: f64) {
+        let mean = F::from_f64(mean);
+        let rescale = F::from_f64(rescale);
+        for f in self.data.iter_mut() {
+            *f = *f * rescale + mean;
+        }
     }
 
-    fn rows(&self) -> slice::Chunks<'_, f64> {
-        self.data.chunks(self.width)
+    fn columns(&self) -> Columns<'_, F> {
+        Columns::new(&self.data, self.width)
     }
 
-    pub fn find_min_max(&self) -> (f64, f64) {
-        let mut min = f64::MAX;
-        let mut max = f64::MIN;
+    pub fn find_min_max(&self) -> (F, F) {
+        let mut min = F::MAX;
+        let mut max = F::MIN;
         self.data.iter().for_each(|f| {
-            min = f64::min(min, *f);
-            max = f64::max(max, *f)
+            if *f < min {
+                min = *f;
+            }
+            if *f > max {
+                max = *f;
+            }
         });
         (min, max)
     }
 
     pub fn find_and_set_min_max(&mut self) {
         let (min, max) = self.find_min_max();
-        log::debug!("Setting min and max value of float image to {}, {}", min, max);
+        log::debug!("Setting min and max value of float image to {:?}, {:?}", min, max);
         self.min_value = min;
         self.max_value = max;
     }
-}
 
-pub struct TwoChannelSubbandCoder<F> {
-    h_lowpass: filter::Filter<F>,
-    h_highpass: filter::Filter<F>,
-    f_lowpass: filter::Filter<F>,
-    f_highpass: filter::Filter<F>,
+    /// Map the samples linearly from the range found by `find_and_set_min_max` onto 0 to 1.
+    pub fn stretch_contrast(&mut self) {
+        self.find_and_set_min_max();
+        let range = self.max_value - self.min_value;
+        for f in self.data.iter_mut() {
+            *f = if range > F::default() { (*f - self.min_value) / range } else { F::default() };
+        }
+        self.min_value = F::from_f64(0.);
+        self.max_value = F::from_f64(1.);
+    }
+}
 
+/// A line of samples in a row-major buffer, a row has stride 1 and a column the width of the image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub start: usize,
+    pub stride: usize,
+    pub len: usize,
 }
 
-impl<F> TwoChannelSubbandCoder<F>
-    where F: Copy + std::ops::Mul<F, Output=F> + std::ops::Neg<Output=F> + std::iter::Sum + Default + std::ops::AddAssign + std::fmt::Debug {
-    pub fn new(h_lowpass: filter::Filter<F>, h_highpass: filter::Filter<F>) -> TwoChannelSubbandCoder<F> {
-        let f_lowpass = h_highpass.invert();
-        let f_highpass = h_lowpass.invert();
-        Self { h_lowpass, h_highpass, f_lowpass, f_highpass }
+impl Line {
+    pub fn row(image_width: usize, x: usize, y: usize, len: usize) -> Self {
+        Self { start: y * image_width + x, stride: 1, len }
+    }
+
+    pub fn column(image_width: usize, x: usize, y: usize, len: usize) -> Self {
+        Self { start: y * image_width + x, stride: image_width, len }
+    }
+
+    fn index(&self, i: usize) -> usize {
+        self.start + i * self.stride
+    }
+
+    /// Index into the line of position `n` of its whole-sample symmetric extension.
+    fn reflect(&self, n: isize) -> usize {
+        let period = 2 * (self.len as isize - 1);
+        let n = n.rem_euclid(period);
+        if n < self.len as isize { n as usize } else { (period - n) as usize }
+    }
+
+    /// Lengths of the lowpass and highpass half, the lowpass half takes the even samples.
+    fn halves(&self) -> (usize, usize) {
+        (self.len.div_ceil(2), self.len / 2)
     }
 }
 
-impl TwoChannelSubbandCoder<f64> {
-    fn downsample(signal: &[f64]) -> Vec<f64> {
-        let mut downsampled = Vec::with_capacity(signal.len() / 2);
-        // signal.into_iter().step_by(2).collect()
-        signal.iter().step_by(2).for_each(|s| downsampled.push(*s));
-        downsampled
+/// Two channel filter bank over whole-sample symmetric filters, the lowpass channel is centered on
+/// the even samples of a line and the highpass channel on the odd samples. Lines are extended by
+/// whole-sample reflection, so other filter symmetries are rejected.
+pub struct TwoChannelSubbandCoder<F> {
+    h_lowpass: Vec<F>,
+    h_highpass: Vec<F>,
+    f_lowpass: Vec<F>,
+    f_highpass: Vec<F>,
+}
+
+impl<F: Sample> TwoChannelSubbandCoder<F> {
+    pub fn new(h_lowpass: filter::Filter<F>, h_highpass: filter::Filter<F>) -> io::Result<TwoChannelSubbandCoder<F>> {
+        for filter in [&h_lowpass, &h_highpass] {
+            if !matches!(filter, filter::Filter::WSS(_)) {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "Only whole-sample symmetric filters are supported"));
+            }
+        }
+        let h_lowpass = h_lowpass.taps();
+        let h_highpass = h_highpass.taps();
+        // f_0(n) = (-1)^n h_1(n)
+        // f_1(n) = (-1)^n h_0(n)
+        let f_lowpass = Self::modulate(&h_highpass);
+        let f_highpass = Self::modulate(&h_lowpass);
+        Ok(Self { h_lowpass, h_highpass, f_lowpass, f_highpass })
+    }
+
+    fn modulate(taps: &[F]) -> Vec<F> {
+        let center = taps.len() / 2;
+        taps.iter().enumerate()
+            .map(|(i, f)| if i.abs_diff(center) % 2 == 1 { F::neg(*f) } else { *f }).collect()
+    }
+
+    /// Output at position `n` of filtering `line` of `signal` with the centered `taps`.
+    fn convolve(taps: &[F], signal: &[F], line: Line, n: usize) -> F {
+        let center = (n + taps.len() / 2) as isize;
+        taps.iter().enumerate()
+            .map(|(k, tap)| *tap * signal[line.index(line.reflect(center - k as isize))])
+            .sum()
     }
 
-    fn upsample(signal: &[f64]) -> Vec<f64> {
-        let mut result = vec![f64::default(); signal.len() * 2];
-        for (i, f) in signal.iter().enumerate() {
-            result[i * 2] = *f;
+    /// One level of analysis of `line` of `signal`, written to the same line of `subbands` with the
+    /// lowpass half first, or the highpass half first when `inverted`.
+    pub fn analysis_line(&self, signal: &[F], subbands: &mut [F], line: Line, inverted: bool) {
+        if line.len < 2 {
+            if line.len == 1 {
+                subbands[line.start] = signal[line.start];
+            }
+            return;
+        }
+        let (low_len, high_len) = line.halves();
+        let (low_start, high_start) = if inverted { (high_len, 0) } else { (0, low_len) };
+        for m in 0..low_len {
+            subbands[line.index(low_start + m)] = Self::convolve(&self.h_lowpass, signal, line, 2 * m);
+        }
+        for m in 0..high_len {
+            subbands[line.index(high_start + m)] = Self::convolve(&self.h_highpass, signal, line, 2 * m + 1);
+        }
+    }
+
+    /// Inverse of `analysis_line`, reconstructs `line` of `signal` from the same line of `subbands`.
+    pub fn synthesis_line(&self, subbands: &[F], signal: &mut [F], line: Line, inverted: bool) {
+        if line.len < 2 {
+            if line.len == 1 {
+                signal[line.start] = subbands[line.start];
+            }
+            return;
+        }
+        let (low_len, high_len) = line.halves();
+        let (low_start, high_start) = if inverted { (high_len, 0) } else { (0, low_len) };
+        for n in 0..line.len {
+            let mut x_hat = F::default();
+            // the upsampled lowpass half is nonzero on the even samples and the highpass half on the odd samples
+            for (taps, parity, start) in [(&self.f_lowpass, 0, low_start), (&self.f_highpass, 1, high_start)] {
+                let center = (n + taps.len() / 2) as isize;
+                for (k, tap) in taps.iter().enumerate() {
+                    let position = line.reflect(center - k as isize);
+                    if position % 2 == parity {
+                        x_hat += *tap * subbands[line.index(start + position / 2)];
+                    }
+                }
+            }
+            signal[line.index(n)] = x_hat;
         }
-        result
+    }
+
+    /// Split the rectangle `split` of the `width` wide `image` into four subbands in place, `scratch`
+    /// receives the result of the row pass and is as large as the image.
+    pub fn split(&self, image: &mut [F], scratch: &mut [F], width: usize, split: &tree::Split) {
+        for y in split.y..split.y + split.height {
+            self.analysis_line(image, scratch, Line::row(width, split.x, y, split.width), split.inverted_rows);
+        }
+        for x in split.x..split.x + split.width {
+            self.analysis_line(scratch, image, Line::column(width, x, split.y, split.height), split.inverted_columns);
+        }
+    }
+
+    /// Inverse of `split`.
+    pub fn join(&self, image: &mut [F], scratch: &mut [F], width: usize, split: &tree::Split) {
+        for x in split.x..split.x + split.width {
+            self.synthesis_line(image, scratch, Line::column(width, x, split.y, split.height), split.inverted_columns);
+        }
+        for y in split.y..split.y + split.height {
+            self.synthesis_line(scratch, image, Line::row(width, split.x, y, split.width), split.inverted_rows);
+        }
+    }
+
+    /// The full WSQ decomposition of `image` into its 64 subbands, in place.
+    pub fn decompose(&self, image: &mut FloatImage<F>) {
+        let mut scratch = vec![F::default(); image.data.len()];
+        for split in tree::wsq_splits(image.width, image.height) {
+            self.split(&mut image.data, &mut scratch, image.width, &split);
+        }
+    }
+
+    /// Reconstruct an image in place from the 64 subbands of its WSQ decomposition.
+    pub fn reconstruct(&self, image: &mut FloatImage<F>) {
+        let mut scratch = vec![F::default(); image.data.len()];
+        for split in tree::wsq_splits(image.width, image.height).iter().rev() {
+            self.join(&mut image.data, &mut scratch, image.width, split);
+        }
+    }
+
+    /// The 64 subbands of `image` packed into one picture in the standard WSQ layout, with the
+    /// contrast of every subband stretched on its own so the low energy subbands stay visible.
+    pub fn mosaic(&self, image: &FloatImage<F>) -> FloatImage<F> {
+        let mut decomposed = image.crop(0, 0, image.width, image.height);
+        self.decompose(&mut decomposed);
+        let mut mosaic = FloatImage::zeros(image.width, image.height);
+        for subband in layout::SubbandLayout::new(image.width, image.height).iter() {
+            let mut band = subband.crop(&decomposed);
+            band.stretch_contrast();
+            mosaic.paste(subband.x, subband.y, &band);
+        }
+        mosaic
+    }
+
+    fn whole(image: &FloatImage<F>) -> tree::Split {
+        tree::Split { x: 0, y: 0, width: image.width, height: image.height, inverted_rows: false, inverted_columns: false }
     }
 }
 
-pub trait Analysis {
-    fn analysis(&self, image: &FloatImage) -> Result<(FloatImage, FloatImage, FloatImage, FloatImage), Box<dyn Error>>;
-    fn row_analysis(&self, image: &FloatImage) -> Result<(FloatImage, FloatImage), Box<dyn std::error::Error>>;
-    fn column_analysis(&self, image: &FloatImage) -> Result<(FloatImage, FloatImage), Box<dyn std::error::Error>>;
-    fn analysis_1d(&self, signal: &[f64]) -> (Vec<f64>, Vec<f64>);
+/// The four subbands of a single level of analysis, in the order lowpass-lowpass, lowpass-highpass,
+/// highpass-lowpass and highpass-highpass.
+pub type Subbands<F = f64> = (FloatImage<F>, FloatImage<F>, FloatImage<F>, FloatImage<F>);
+
+pub trait Analysis<F = f64> {
+    fn analysis(&self, image: &FloatImage<F>) -> Result<Subbands<F>, Box<dyn Error>>;
+    fn row_analysis(&self, image: &FloatImage<F>) -> Result<(FloatImage<F>, FloatImage<F>), Box<dyn std::error::Error>>;
+    fn column_analysis(&self, image: &FloatImage<F>) -> Result<(FloatImage<F>, FloatImage<F>), Box<dyn std::error::Error>>;
+    fn analysis_1d(&self, signal: &[F]) -> (Vec<F>, Vec<F>);
 }
 
-pub trait Synthesis {
-    fn synthesis_1d(&self, a_0: &[f64], a_1: &[f64]) -> Vec<f64>;
-    fn synthesis(&self, a: &(FloatImage, FloatImage, FloatImage, FloatImage)) -> Result<FloatImage, Box<dyn Error>>;
-    fn row_synthesis(&self, image_lowpass: &FloatImage, image_highpass: &FloatImage) -> Result<FloatImage, Box<dyn Error>>;
-    fn column_synthesis(&self, image_lowpass: &FloatImage, image_highpass: &FloatImage) -> Result<FloatImage, Box<dyn Error>>;
+pub trait Synthesis<F = f64> {
+    fn synthesis_1d(&self, a_0: &[F], a_1: &[F]) -> Vec<F>;
+    fn synthesis(&self, a: &Subbands<F>) -> Result<FloatImage<F>, Box<dyn Error>>;
+    fn row_synthesis(&self, image_lowpass: &FloatImage<F>, image_highpass: &FloatImage<F>) -> Result<FloatImage<F>, Box<dyn Error>>;
+    fn column_synthesis(&self, image_lowpass: &FloatImage<F>, image_highpass: &FloatImage<F>) -> Result<FloatImage<F>, Box<dyn Error>>;
 }
 
-impl Analysis for TwoChannelSubbandCoder<f64> {
-    fn analysis(&self, image: &FloatImage) -> Result<(FloatImage, FloatImage, FloatImage, FloatImage), Box<dyn Error>> {
-        let a_row = self.row_analysis(image)?;
-        let a_0 = self.column_analysis(&a_row.0)?;
-        let a_1 = self.column_analysis(&a_row.1)?;
-        Ok((a_0.0, a_0.1, a_1.0, a_1.1))
-    }
-    fn row_analysis(&self, image: &FloatImage) -> Result<(FloatImage, FloatImage), Box<dyn std::error::Error>> {
-        let mut rows_lowpass = vec![];
-        let mut rows_highpass = vec![];
-
-        for row in image.rows() {
-            let (lowpassed, highpassed) = self.analysis_1d(row);
-            rows_lowpass.push(lowpassed);
-            rows_highpass.push(highpassed);
-        }
-        let rows_lowpass_image = FloatImage::from(rows_lowpass);
-        let rows_highpass_image = FloatImage::from(rows_highpass);
-        Ok((rows_lowpass_image, rows_highpass_image))
-    }
-    fn column_analysis(&self, image: &FloatImage) -> Result<(FloatImage, FloatImage), Box<dyn std::error::Error>> {
-        let mut cols_lowpass = vec![];
-        let mut cols_highpass = vec![];
-
-        for col in image.columns() {
-            let (lowpassed, highpassed) = self.analysis_1d(&col);
-            cols_lowpass.push(lowpassed);
-            cols_highpass.push(highpassed);
-        }
-        let mut rows_lowpass_image = FloatImage::from(cols_lowpass);
-        let mut rows_highpass_image = FloatImage::from(cols_highpass);
-        rows_lowpass_image.rotate();
-        rows_highpass_image.rotate();
-        Ok((rows_lowpass_image, rows_highpass_image))
-    }
-
-    fn analysis_1d(&self, signal: &[f64]) -> (Vec<f64>, Vec<f64>) {
-        let lowpassed = self.h_lowpass.apply(signal);
-        let highpassed = self.h_highpass.apply(signal);
-        let lowpassed = Self::downsample(&lowpassed);
-        let highpassed = Self::downsample(&highpassed);
-        (lowpassed, highpassed)
+impl<F: Sample> Analysis<F> for TwoChannelSubbandCoder<F> {
+    fn analysis(&self, image: &FloatImage<F>) -> Result<Subbands<F>, Box<dyn Error>> {
+        let mut subbands = image.crop(0, 0, image.width, image.height);
+        let mut scratch = vec![F::default(); image.data.len()];
+        let split = Self::whole(image);
+        self.split(&mut subbands.data, &mut scratch, image.width, &split);
+        let [low_low, high_low, low_high, high_high] = split.quadrants().map(|q| subbands.crop(q.x, q.y, q.width, q.height));
+        Ok((low_low, low_high, high_low, high_high))
+    }
+    fn row_analysis(&self, image: &FloatImage<F>) -> Result<(FloatImage<F>, FloatImage<F>), Box<dyn std::error::Error>> {
+        let mut subbands = image.crop(0, 0, image.width, image.height);
+        for y in 0..image.height {
+            self.analysis_line(&image.data, &mut subbands.data, Line::row(image.width, 0, y, image.width), false);
+        }
+        let low_width = image.width.div_ceil(2);
+        Ok((subbands.crop(0, 0, low_width, image.height), subbands.crop(low_width, 0, image.width - low_width, image.height)))
+    }
+    fn column_analysis(&self, image: &FloatImage<F>) -> Result<(FloatImage<F>, FloatImage<F>), Box<dyn std::error::Error>> {
+        let mut subbands = image.crop(0, 0, image.width, image.height);
+        for x in 0..image.width {
+            self.analysis_line(&image.data, &mut subbands.data, Line::column(image.width, x, 0, image.height), false);
+        }
+        let low_height = image.height.div_ceil(2);
+        Ok((subbands.crop(0, 0, image.width, low_height), subbands.crop(0, low_height, image.width, image.height - low_height)))
+    }
+
+    fn analysis_1d(&self, signal: &[F]) -> (Vec<F>, Vec<F>) {
+        let mut subbands = vec![F::default(); signal.len()];
+        self.analysis_line(signal, &mut subbands, Line { start: 0, stride: 1, len: signal.len() }, false);
+        let highpassed = subbands.split_off(signal.len().div_ceil(2));
+        (subbands, highpassed)
     }
 }
 
-impl Synthesis for TwoChannelSubbandCoder<f64> {
-    fn synthesis_1d(&self, a_0: &[f64], a_1: &[f64]) -> Vec<f64> {
-        let a_0 = Self::upsample(a_0);
-        let a_1 = Self::upsample(a_1);
-        let x_hat_0 = self.f_lowpass.apply(&a_0);
-        let x_hat_1 = self.f_highpass.apply(&a_1);
-        x_hat_0.iter().zip(x_hat_1).map(|(x_0, x_1)| *x_0 + x_1).collect()
+impl<F: Sample> Synthesis<F> for TwoChannelSubbandCoder<F> {
+    fn synthesis_1d(&self, a_0: &[F], a_1: &[F]) -> Vec<F> {
+        let subbands = [a_0, a_1].concat();
+        let mut x_hat = vec![F::default(); subbands.len()];
+        self.synthesis_line(&subbands, &mut x_hat, Line { start: 0, stride: 1, len: subbands.len() }, false);
+        x_hat
     }
 
+    fn synthesis(&self, a: &Subbands<F>) -> Result<FloatImage<F>, Box<dyn Error>> {
+        let mut x_hat = FloatImage::zeros(a.0.width + a.2.width, a.0.height + a.1.height);
+        x_hat.paste(0, 0, &a.0);
+        x_hat.paste(0, a.0.height, &a.1);
+        x_hat.paste(a.0.width, 0, &a.2);
+        x_hat.paste(a.0.width, a.0.height, &a.3);
+        let mut scratch = vec![F::default(); x_hat.data.len()];
+        let split = Self::whole(&x_hat);
+        self.join(&mut x_hat.data, &mut scratch, x_hat.width, &split);
+        Ok(x_hat)
+    }
+    fn row_synthesis(&self, image_lowpass: &FloatImage<F>, image_highpass: &FloatImage<F>) -> Result<FloatImage<F>, Box<dyn Error>> {
+        let mut subbands = FloatImage::zeros(image_lowpass.width + image_highpass.width, image_lowpass.height);
+        subbands.paste(0, 0, image_lowpass);
+        subbands.paste(image_lowpass.width, 0, image_highpass);
+        let mut x_hat = FloatImage::zeros(subbands.width, subbands.height);
+        for y in 0..subbands.height {
+            self.synthesis_line(&subbands.data, &mut x_hat.data, Line::row(subbands.width, 0, y, subbands.width), false);
+        }
+        Ok(x_hat)
+    }
 
-    fn synthesis(&self, a: &(FloatImage, FloatImage, FloatImage, FloatImage)) -> Result<FloatImage, Box<dyn Error>> {
-        let y_0 = self.row_synthesis(&a.0, &a.1)?;
-        let y_1 = self.row_synthesis(&a.2, &a.3)?;
-        let x_hat = self.column_synthesis(&y_0, &y_1)?;
+    fn column_synthesis(&self, image_lowpass: &FloatImage<F>, image_highpass: &FloatImage<F>) -> Result<FloatImage<F>, Box<dyn Error>> {
+        let mut subbands = FloatImage::zeros(image_lowpass.width, image_lowpass.height + image_highpass.height);
+        subbands.paste(0, 0, image_lowpass);
+        subbands.paste(0, image_lowpass.height, image_highpass);
+        let mut x_hat = FloatImage::zeros(subbands.width, subbands.height);
+        for x in 0..subbands.width {
+            self.synthesis_line(&subbands.data, &mut x_hat.data, Line::column(subbands.width, x, 0, subbands.height), false);
+        }
         Ok(x_hat)
     }
-    fn row_synthesis(&self, image_lowpass: &FloatImage, image_highpass: &FloatImage) -> Result<FloatImage, Box<dyn Error>> {
-        let mut data = vec![];
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::quantization::{dequantizer, quantizer};
+    use crate::swt::{Analysis, FloatImage, Sample, Synthesis, TwoChannelSubbandCoder};
+    use crate::swt::filter::Filter;
+    use crate::swt::fixed::Fixed;
+    use crate::swt::layout::SubbandLayout;
+
+    fn wsq_coder<F: Sample>() -> TwoChannelSubbandCoder<F> {
+        let lowpass = [0.85269867900940, 0.37740285561265, -0.11062440441842, -0.02384946501938, 0.037828455506995];
+        let highpass = [0.78848561640566, -0.41809227322221, -0.040689417609558, 0.064538882628938];
+        TwoChannelSubbandCoder::new(Filter::WSS(lowpass.iter().map(|c| F::from_f64(*c)).collect()),
+                                    Filter::WSS(highpass.iter().map(|c| F::from_f64(*c)).collect())).unwrap()
+    }
+
+    fn round_trip<F: Sample>(image: &FloatImage) -> Vec<f64> {
+        let mut image = image.convert::<F>();
+        let (mean, rescale) = image.get_mean_and_rescale();
+        image.normalize(mean, rescale);
+        let coder = wsq_coder::<F>();
+        let subbands = coder.analysis(&image).unwrap();
+        let reconstructed = coder.synthesis(&subbands).unwrap();
+        reconstructed.data.iter().map(|f| f.to_f64() * rescale + mean).collect()
+    }
 
-        for (a_0, a_1) in image_lowpass.rows().zip(image_highpass.rows()) {
-            let reconstructed = self.synthesis_1d(a_0, a_1);
-            data.push(reconstructed);
+    #[test]
+    fn test_f32_tracks_f64() {
+        let image = noise_image(64, 48);
+        let reconstructed_f64 = round_trip::<f64>(&image);
+        let reconstructed_f32 = round_trip::<f32>(&image);
+        for (a, b) in reconstructed_f64.iter().zip(&reconstructed_f32) {
+            assert!(f64::abs(a - b) < 1e-3, "{} and {} differ", a, b);
         }
+    }
 
-        let mut result = FloatImage::from(data);
-        result.max_value = (image_highpass.max_value + image_lowpass.max_value) / 2.;
-        Ok(result)
+    #[test]
+    fn test_rejects_half_sample_filters() {
+        let filters = || (Filter::WSS(vec![0.5, 0.25]), Filter::WSS(vec![0.5, -0.25]));
+        assert!(TwoChannelSubbandCoder::new(filters().0, filters().1).is_ok());
+        for (lowpass, highpass) in [(Filter::HSS(vec![0.5, 0.25]), filters().1), (filters().0, Filter::HSA(vec![0.5, 0.25])),
+                                    (filters().0, Filter::WSA(vec![0.5, 0.25]))] {
+            let error = TwoChannelSubbandCoder::<f64>::new(lowpass, highpass).err().unwrap();
+            assert_eq!(std::io::ErrorKind::Unsupported, error.kind());
+        }
     }
 
-    fn column_synthesis(&self, image_lowpass: &FloatImage, image_highpass: &FloatImage) -> Result<FloatImage, Box<dyn Error>> {
-        let mut data = vec![];
+    fn noise_image(width: usize, height: usize) -> FloatImage {
+        let data = (0..width * height).map(|i| ((i * 7919) % 256) as f64).collect();
+        FloatImage { data, width, height, min_value: 0., max_value: 255. }
+    }
 
-        for (a_0, a_1) in image_lowpass.columns().zip(image_highpass.columns()) {
-            let reconstructed = self.synthesis_1d(&a_0, &a_1);
-            data.push(reconstructed);
+    #[test]
+    fn test_perfect_reconstruction() {
+        for (width, height) in [(64, 48), (37, 53)] {
+            let image = noise_image(width, height);
+            for (a, b) in round_trip::<f64>(&image).iter().zip(&image.data) {
+                assert!(f64::abs(a - b) < 1e-9, "{} and {} differ", a, b);
+            }
+            let mut decomposed = image.convert::<f64>();
+            let coder = wsq_coder::<f64>();
+            coder.decompose(&mut decomposed);
+            coder.reconstruct(&mut decomposed);
+            for (a, b) in decomposed.data.iter().zip(&image.data) {
+                assert!(f64::abs(a - b) < 1e-9, "{} and {} differ", a, b);
+            }
         }
+    }
 
-        let mut result = FloatImage::from(data);
-        result.rotate();
-        result.max_value = (image_highpass.max_value + image_lowpass.max_value) / 2.;
-        Ok(result)
+    #[test]
+    fn test_analysis_1d_matches_analysis() {
+        let image = noise_image(17, 1);
+        let (lowpassed, highpassed) = wsq_coder::<f64>().analysis_1d(&image.data);
+        let (rows_lowpass, rows_highpass) = wsq_coder::<f64>().row_analysis(&image).unwrap();
+        assert_eq!((9, 8), (lowpassed.len(), highpassed.len()));
+        assert_eq!(lowpassed, rows_lowpass.data);
+        assert_eq!(highpassed, rows_highpass.data);
     }
-}
 
+    fn decode<F: Sample>(indices: &[i32], width: usize, height: usize, bin_width: f64, mean: f64, rescale: f64) -> Vec<f64> {
+        let data = dequantizer::dequantize_subband::<F>(indices, bin_width, 1.2 * bin_width, 0.44);
+        let mut reconstructed = FloatImage { data, width, height, min_value: F::default(), max_value: F::default() };
+        wsq_coder::<F>().reconstruct(&mut reconstructed);
+        reconstructed.denormalize(mean, rescale);
+        reconstructed.data.iter().map(|f| f.to_f64()).collect()
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::swt::FloatImage;
+    #[test]
+    fn test_fixed_decoder_deviation() {
+        let mut image = noise_image(64, 48);
+        let (mean, rescale) = image.get_mean_and_rescale();
+        image.normalize(mean, rescale);
+        wsq_coder::<f64>().decompose(&mut image);
+        for bin_width in [0.1, 1., 4.] {
+            let indices = quantizer::quantize_subband(&image.data, bin_width, 1.2 * bin_width);
+            let decoded_f64 = decode::<f64>(&indices, image.width, image.height, bin_width, mean, rescale);
+            let decoded_fixed = decode::<Fixed>(&indices, image.width, image.height, bin_width, mean, rescale);
+            for (a, b) in decoded_f64.iter().zip(&decoded_fixed) {
+                assert!(f64::abs(a - b) <= Fixed::MAX_DEVIATION, "{} and {} differ", a, b);
+            }
+        }
+    }
+
+    fn quantized_round_trip<F: Sample>(image: &FloatImage, bin_width: Option<f64>) -> Vec<f64> {
+        let mut image = image.convert::<F>();
+        let (mean, rescale) = image.get_mean_and_rescale();
+        image.normalize(mean, rescale);
+        let coder = wsq_coder::<F>();
+        coder.decompose(&mut image);
+        if let Some(bin_width) = bin_width {
+            let indices = quantizer::quantize_subband(&image.data, bin_width, 1.2 * bin_width);
+            image.data = dequantizer::dequantize_subband::<F>(&indices, bin_width, 1.2 * bin_width, 0.44);
+        }
+        coder.reconstruct(&mut image);
+        image.denormalize(mean, rescale);
+        image.data.iter().map(|f| f.to_f64()).collect()
+    }
+
+    fn max_deviation(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(a, b)| f64::abs(a - b)).fold(0., f64::max)
+    }
+
+    fn rounding_differently(a: &[f64], b: &[f64]) -> usize {
+        a.iter().zip(b).filter(|(a, b)| a.round() != b.round()).count()
+    }
+
+    // The figures of the precision section in the readme.
+    #[test]
+    fn test_decoder_precision() {
+        let image = crate::codec::tests::fingerprint(512, 512).to_float_image::<f64>();
+        let mse = |decoded: &[f64]| decoded.iter().zip(&image.data).map(|(a, b)| (a - b) * (a - b)).sum::<f64>() / decoded.len() as f64;
+        for (bin_width, deviation, rounding) in [(None, 2e-4, 0), (Some(0.5), 0.15, 0), (Some(2.), 0.45, 50)] {
+            let decoded_f64 = quantized_round_trip::<f64>(&image, bin_width);
+            let decoded_f32 = quantized_round_trip::<f32>(&image, bin_width);
+            assert!(max_deviation(&decoded_f64, &decoded_f32) < deviation, "f32 with bin width {:?}", bin_width);
+            assert!(rounding_differently(&decoded_f64, &decoded_f32) <= rounding, "f32 with bin width {:?}", bin_width);
+            assert!(f64::abs(mse(&decoded_f64) - mse(&decoded_f32)) < 1e-5, "f32 with bin width {:?}", bin_width);
+        }
+
+        let mut decomposed = image.convert::<f64>();
+        let (mean, rescale) = decomposed.get_mean_and_rescale();
+        decomposed.normalize(mean, rescale);
+        wsq_coder::<f64>().decompose(&mut decomposed);
+        for (bin_width, rounding) in [(0.5, 10), (2., 1400)] {
+            let indices = quantizer::quantize_subband(&decomposed.data, bin_width, 1.2 * bin_width);
+            let decoded_f64 = decode::<f64>(&indices, image.width, image.height, bin_width, mean, rescale);
+            let decoded_fixed = decode::<Fixed>(&indices, image.width, image.height, bin_width, mean, rescale);
+            assert!(max_deviation(&decoded_f64, &decoded_fixed) < 0.02, "fixed with bin width {}", bin_width);
+            assert!(rounding_differently(&decoded_f64, &decoded_fixed) <= rounding, "fixed with bin width {}", bin_width);
+        }
+    }
+
+    #[test]
+    fn test_mosaic() {
+        let image = noise_image(64, 48);
+        let mosaic = wsq_coder::<f64>().mosaic(&image);
+        assert_eq!((64, 48), (mosaic.width, mosaic.height));
+        for subband in SubbandLayout::new(64, 48).iter() {
+            let band = subband.crop(&mosaic);
+            let (min, max) = band.find_min_max();
+            if band.data.len() > 1 {
+                assert_eq!((0., 1.), (min, max), "subband {}", subband.index);
+            }
+        }
+    }
 
     #[test]
     fn test_columns() {
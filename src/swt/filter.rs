@@ -16,6 +16,12 @@ impl<F> Filter<F>
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Filter::WSS(coefficients) | Filter::WSA(coefficients) | Filter::HSS(coefficients) | Filter::HSA(coefficients) => coefficients.is_empty()
+        }
+    }
+
     pub fn apply(&self, signal: &[F]) -> Vec<F> {
         let coefficients = self.coefficients();
         let signal_extension = match self {
@@ -41,6 +47,11 @@ impl<F> Filter<F>
         result
     }
 
+    /// All coefficients of the filter, centered on the middle one.
+    pub fn taps(&self) -> Vec<F> {
+        self.coefficients().collect()
+    }
+
     fn coefficients(&self) -> FilterIter<'_, F> {
         match self {
             Filter::WSA(coefficients) => FilterExtension::WholeSampleHighpass(coefficients).into_iter(),
@@ -0,0 +1,144 @@
+//! Fixed-point sample type for decoding on targets without a floating point unit.
+//
+// Samples are stored as signed Q15.16 numbers in an `i32`, products are computed in `i64` and
+// shifted back. All arithmetic saturates instead of wrapping, so a corrupt file can not flip the
+// sign of a pixel. The only floating point operations are the conversions in `Sample::from_f64`
+// and `Sample::to_f64`, which the decoder uses once per table entry and not per coefficient.
+//
+// Decoding with `Fixed` as sample type deviates from decoding in `f64` by at most
+// `Fixed::MAX_DEVIATION` grey levels before the reconstructed pixels are rounded.
+use crate::swt::Sample;
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i32);
+
+impl Fixed {
+    pub const FRACTIONAL_BITS: u32 = 16;
+    pub const ONE: Fixed = Fixed(1 << Self::FRACTIONAL_BITS);
+    /// Largest difference in grey levels between a pixel decoded with `Fixed` and with `f64`
+    /// samples, before rounding.
+    pub const MAX_DEVIATION: f64 = 0.05;
+
+    pub const fn from_bits(bits: i32) -> Self {
+        Fixed(bits)
+    }
+
+    pub const fn to_bits(self) -> i32 {
+        self.0
+    }
+
+    fn saturate(value: i64) -> Self {
+        Fixed(value.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+}
+
+impl std::fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_f64())
+    }
+}
+
+impl std::ops::Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl std::ops::AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Fixed) {
+        *self = *self + rhs;
+    }
+}
+
+impl std::ops::Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl std::ops::Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, rhs: Fixed) -> Fixed {
+        let product = self.0 as i64 * rhs.0 as i64;
+        Self::saturate((product + (1 << (Self::FRACTIONAL_BITS - 1))) >> Self::FRACTIONAL_BITS)
+    }
+}
+
+impl std::ops::Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, rhs: Fixed) -> Fixed {
+        if rhs.0 == 0 {
+            return if self.0 < 0 { Fixed(i32::MIN) } else { Fixed(i32::MAX) };
+        }
+        Self::saturate(((self.0 as i64) << Self::FRACTIONAL_BITS) / rhs.0 as i64)
+    }
+}
+
+impl std::ops::Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(self.0.saturating_neg())
+    }
+}
+
+impl std::iter::Sum for Fixed {
+    fn sum<I: Iterator<Item=Fixed>>(iter: I) -> Fixed {
+        iter.fold(Fixed::default(), |a, b| a + b)
+    }
+}
+
+impl Sample for Fixed {
+    const MIN: Self = Fixed(i32::MIN);
+    const MAX: Self = Fixed(i32::MAX);
+
+    fn from_f64(value: f64) -> Self {
+        Self::saturate((value * Self::ONE.0 as f64).round() as i64)
+    }
+
+    fn from_i32(value: i32) -> Self {
+        Self::saturate((value as i64) << Self::FRACTIONAL_BITS)
+    }
+
+    fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::ONE.0 as f64
+    }
+
+    fn round_half_up(self) -> i32 {
+        ((self.0 as i64 + Self::ONE.0 as i64 / 2) >> Self::FRACTIONAL_BITS) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fixed;
+    use crate::swt::Sample;
+
+    #[test]
+    fn test_arithmetic() {
+        let a = Fixed::from_f64(1.5);
+        let b = Fixed::from_f64(-0.25);
+        assert_eq!(1.25, (a + b).to_f64());
+        assert_eq!(1.75, (a - b).to_f64());
+        assert_eq!(-0.375, (a * b).to_f64());
+        assert_eq!(-6., (a / b).to_f64());
+        assert_eq!(Fixed::MAX, Fixed::from_i32(30000) * Fixed::from_i32(30000));
+    }
+
+    #[test]
+    fn test_round_half_up() {
+        assert_eq!(2, Fixed::from_f64(1.5).round_half_up());
+        assert_eq!(1, Fixed::from_f64(1.49).round_half_up());
+        assert_eq!(-1, Fixed::from_f64(-1.5).round_half_up());
+        assert_eq!(-1, Fixed::from_f64(-1.49).round_half_up());
+        assert_eq!(-2, Fixed::from_f64(-1.51).round_half_up());
+        assert_eq!(1.5f64.round_half_up(), Fixed::from_f64(1.5).round_half_up());
+        assert_eq!((-1.5f64).round_half_up(), Fixed::from_f64(-1.5).round_half_up());
+    }
+}
@@ -0,0 +1,183 @@
+//! Position and size of the 64 subbands of the WSQ decomposition
+//
+// The subbands are numbered in the order the decomposition tree is walked depth first, visiting the
+// quadrants of every split top left, top right, bottom left, bottom right. The lowest frequencies
+// get the lowest numbers and the four subbands of the highest frequencies, 60 to 63, are never
+// transmitted.
+use std::fmt;
+
+use crate::swt::FloatImage;
+use crate::swt::tree::{Node, Split, WSQ_TREE};
+
+pub const NUM_SUBBANDS: usize = 64;
+/// Subbands from this index on are discarded by the encoder.
+pub const NUM_QUANTIZED_SUBBANDS: usize = 60;
+
+/// Filters applied to the rows and the columns of a split, the first letter is the rows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Band {
+    LL,
+    HL,
+    LH,
+    HH,
+}
+
+impl Band {
+    fn new(rows_highpass: bool, columns_highpass: bool) -> Self {
+        match (rows_highpass, columns_highpass) {
+            (false, false) => Band::LL,
+            (true, false) => Band::HL,
+            (false, true) => Band::LH,
+            (true, true) => Band::HH,
+        }
+    }
+}
+
+impl fmt::Display for Band {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subband {
+    pub index: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// The band taken at every level of the decomposition, from the whole image down to this subband.
+    pub path: Vec<Band>,
+}
+
+impl Subband {
+    pub fn is_quantized(&self) -> bool {
+        self.index < NUM_QUANTIZED_SUBBANDS
+    }
+
+    pub fn is_discarded(&self) -> bool {
+        !self.is_quantized()
+    }
+
+    pub fn level(&self) -> usize {
+        self.path.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The path written out, e.g. `LL→HL→HL→LL`.
+    pub fn path_name(&self) -> String {
+        self.path.iter().map(|b| b.to_string()).collect::<Vec<_>>().join("→")
+    }
+
+    /// Copy of this subband out of a decomposed image.
+    pub fn crop<F: crate::swt::Sample>(&self, image: &FloatImage<F>) -> FloatImage<F> {
+        image.crop(self.x, self.y, self.width, self.height)
+    }
+}
+
+/// The 64 subbands of the WSQ decomposition of a `width` by `height` image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubbandLayout {
+    pub width: usize,
+    pub height: usize,
+    subbands: Vec<Subband>,
+}
+
+impl SubbandLayout {
+    pub fn new(width: usize, height: usize) -> Self {
+        fn visit(node: &Node, region: Split, path: &mut Vec<Band>, subbands: &mut Vec<Subband>) {
+            match node {
+                Node::Leaf => subbands.push(Subband {
+                    index: subbands.len(),
+                    x: region.x,
+                    y: region.y,
+                    width: region.width,
+                    height: region.height,
+                    path: path.clone(),
+                }),
+                Node::Split(children) => {
+                    for (i, (child, quadrant)) in children.iter().zip(region.quadrants()).enumerate() {
+                        let (right, bottom) = (i % 2 == 1, i / 2 == 1);
+                        path.push(Band::new(right != region.inverted_rows, bottom != region.inverted_columns));
+                        visit(child, quadrant, path, subbands);
+                        path.pop();
+                    }
+                }
+            }
+        }
+        let mut subbands = Vec::with_capacity(NUM_SUBBANDS);
+        let image = Split { x: 0, y: 0, width, height, inverted_rows: false, inverted_columns: false };
+        visit(&WSQ_TREE, image, &mut vec![], &mut subbands);
+        Self { width, height, subbands }
+    }
+
+    pub fn subbands(&self) -> &[Subband] {
+        &self.subbands
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Subband> {
+        self.subbands.get(index)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Subband> {
+        self.subbands.iter()
+    }
+
+    /// The subbands the encoder transmits.
+    pub fn quantized(&self) -> impl Iterator<Item=&Subband> {
+        self.subbands.iter().filter(|s| s.is_quantized())
+    }
+}
+
+impl<'a> IntoIterator for &'a SubbandLayout {
+    type Item = &'a Subband;
+    type IntoIter = std::slice::Iter<'a, Subband>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.subbands.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Band, SubbandLayout, NUM_SUBBANDS};
+
+    #[test]
+    fn test_layout_covers_image() {
+        let layout = SubbandLayout::new(545, 622);
+        assert_eq!(NUM_SUBBANDS, layout.subbands().len());
+        assert_eq!(545 * 622, layout.iter().map(|s| s.len()).sum::<usize>());
+        let mut covered = vec![0; 545 * 622];
+        for subband in &layout {
+            for y in subband.y..subband.y + subband.height {
+                for x in subband.x..subband.x + subband.width {
+                    covered[y * 545 + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|c| *c == 1));
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = SubbandLayout::new(545, 622);
+        let lowest = layout.get(0).unwrap();
+        assert_eq!((0, 0, 18, 20), (lowest.x, lowest.y, lowest.width, lowest.height));
+        assert_eq!(vec![Band::LL; 5], lowest.path);
+        let inverted = layout.get(19).unwrap();
+        assert_eq!((137, 0, 34, 39), (inverted.x, inverted.y, inverted.width, inverted.height));
+        assert_eq!("LL→HL→HL→LL", inverted.path_name());
+        let highest = layout.get(63).unwrap();
+        assert_eq!((409, 466, 136, 156), (highest.x, highest.y, highest.width, highest.height));
+        assert_eq!(2, highest.level());
+        assert!(highest.is_discarded());
+        assert_eq!(60, layout.quantized().count());
+    }
+}
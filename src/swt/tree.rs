@@ -0,0 +1,95 @@
+//! The WSQ decomposition tree
+//
+// The image is split into four subbands by one level of row and column analysis, and some of these
+// subbands are split again, five levels deep for the lowest frequencies. Every split places the
+// lowpass half of a line first, unless the line is "inverted", then the highpass half comes first.
+// A subband is inverted in the rows when it lies right of its sibling and in the columns when it
+// lies below its sibling, this is what gives the 64 subbands of the standard their layout.
+
+/// Which of the four quadrants of a split, top left, top right, bottom left, bottom right, are split again.
+#[derive(Debug)]
+pub(crate) enum Node {
+    Leaf,
+    Split([&'static Node; 4]),
+}
+
+static LEAF: Node = Node::Leaf;
+static SPLIT_ONCE: Node = Node::Split([&LEAF; 4]);
+static SPLIT_TWICE: Node = Node::Split([&SPLIT_ONCE; 4]);
+static LOWEST: Node = Node::Split([&SPLIT_ONCE, &LEAF, &LEAF, &LEAF]);
+static LOW: Node = Node::Split([&LOWEST, &SPLIT_ONCE, &SPLIT_ONCE, &SPLIT_ONCE]);
+static LOW_QUADRANT: Node = Node::Split([&LOW, &SPLIT_TWICE, &SPLIT_TWICE, &LEAF]);
+pub(crate) static WSQ_TREE: Node = Node::Split([&LOW_QUADRANT, &SPLIT_ONCE, &SPLIT_ONCE, &SPLIT_ONCE]);
+
+/// A rectangle of the image that is split into four subbands by one level of analysis.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Split {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub inverted_rows: bool,
+    pub inverted_columns: bool,
+}
+
+impl Split {
+    /// Width of the left half, the lowpass half unless the rows are inverted.
+    pub fn left_width(&self) -> usize {
+        if self.inverted_rows { self.width / 2 } else { self.width.div_ceil(2) }
+    }
+
+    /// Height of the top half, the lowpass half unless the columns are inverted.
+    pub fn top_height(&self) -> usize {
+        if self.inverted_columns { self.height / 2 } else { self.height.div_ceil(2) }
+    }
+
+    /// The four quadrants in the order top left, top right, bottom left, bottom right.
+    pub fn quadrants(&self) -> [Split; 4] {
+        let (left, top) = (self.left_width(), self.top_height());
+        let (right, bottom) = (self.width - left, self.height - top);
+        let quadrant = |x, y, width, height, inverted_rows, inverted_columns| Split { x, y, width, height, inverted_rows, inverted_columns };
+        [
+            quadrant(self.x, self.y, left, top, false, false),
+            quadrant(self.x + left, self.y, right, top, true, false),
+            quadrant(self.x, self.y + top, left, bottom, false, true),
+            quadrant(self.x + left, self.y + top, right, bottom, true, true),
+        ]
+    }
+}
+
+/// All splits of the WSQ decomposition of a `width` by `height` image, every split comes before the
+/// splits of its quadrants.
+pub fn wsq_splits(width: usize, height: usize) -> Vec<Split> {
+    fn visit(node: &Node, region: Split, splits: &mut Vec<Split>) {
+        if let Node::Split(children) = node {
+            splits.push(region);
+            for (child, quadrant) in children.iter().zip(region.quadrants()) {
+                visit(child, quadrant, splits);
+            }
+        }
+    }
+    let mut splits = vec![];
+    let image = Split { x: 0, y: 0, width, height, inverted_rows: false, inverted_columns: false };
+    visit(&WSQ_TREE, image, &mut splits);
+    splits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wsq_splits;
+
+    #[test]
+    fn test_wsq_splits() {
+        let splits = wsq_splits(545, 622);
+        assert_eq!(21, splits.len());
+        assert_eq!((0, 0, 545, 622), (splits[0].x, splits[0].y, splits[0].width, splits[0].height));
+        // the lowest frequencies are split five levels deep
+        let lowest = splits[4];
+        assert_eq!((0, 0, 35, 39), (lowest.x, lowest.y, lowest.width, lowest.height));
+        // right of the lowpass quadrant the highpass half of the rows comes first
+        let inverted = splits[8];
+        assert_eq!((137, 0, 136, 156), (inverted.x, inverted.y, inverted.width, inverted.height));
+        assert!(inverted.inverted_rows && !inverted.inverted_columns);
+        assert_eq!(68, inverted.left_width());
+    }
+}
@@ -0,0 +1,68 @@
+//! SDL windows showing grey scale images, only built with the `viewer` feature
+use std::error::Error;
+use std::time::Duration;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use wsq::pnm;
+
+struct ImageWindow {
+    canvas: Canvas<Window>,
+    rgb: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+pub struct SdlDisplay {
+    context: sdl2::Sdl,
+    video: sdl2::VideoSubsystem,
+    windows: Vec<ImageWindow>,
+}
+
+impl SdlDisplay {
+    pub fn try_new() -> Result<Self, Box<dyn Error>> {
+        let context = sdl2::init()?;
+        let video = context.video()?;
+        Ok(Self { context, video, windows: vec![] })
+    }
+
+    pub fn display(&mut self, image: &pnm::Image, title: &str) -> Result<(), Box<dyn Error>> {
+        let (width, height) = (image.width as u32, image.height as u32);
+        let window = self.video.window(title, width, height).position_centered().build()?;
+        let canvas = window.into_canvas().build()?;
+        let rgb = image.data.iter()
+            .flat_map(|s| [(*s as u32 * 255 / image.max_value as u32) as u8; 3])
+            .collect();
+        self.windows.push(ImageWindow { canvas, rgb, width, height });
+        self.draw()
+    }
+
+    fn draw(&mut self) -> Result<(), Box<dyn Error>> {
+        for window in self.windows.iter_mut() {
+            let texture_creator = window.canvas.texture_creator();
+            let mut texture = texture_creator.create_texture_static(PixelFormatEnum::RGB24, window.width, window.height)?;
+            texture.update(None, &window.rgb, window.width as usize * 3)?;
+            window.canvas.copy(&texture, None, None)?;
+            window.canvas.present();
+        }
+        Ok(())
+    }
+
+    /// Redraw the windows until they are closed or escape is pressed.
+    pub fn wait_for_exit(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut event_pump = self.context.event_pump()?;
+        loop {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return Ok(()),
+                    _ => {}
+                }
+            }
+            self.draw()?;
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}